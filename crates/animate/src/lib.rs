@@ -61,3 +61,17 @@ pub extern "C" fn frame(
         true
     }
 }
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub extern "C" fn command(command: &str) {
+    if !points::command(command) {
+        log::error!("{command}: not a valid animate command!");
+    }
+}
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub extern "C" fn language(language: &str) {
+    i18n::set_stream_language(language);
+}
@@ -139,8 +139,10 @@ pub extern "C" fn frame(
 ) -> bool {
     let mut state = STATE.lock().unwrap();
     let state = state.as_mut().unwrap();
-    let height =
-        height - points::make_bottom_banner(&context, width, height, time_left, last_event);
+    let height = height
+        - points::make_bottom_banner(
+            &context, width, height, time_left, last_event, "place", None,
+        );
 
     if state.image.width() != WIDTH || state.image.height() != HEIGHT {
         state.image.resize(WIDTH, HEIGHT, Pixel::blank());
@@ -177,7 +179,21 @@ pub extern "C" fn frame(
     true
 }
 
-async fn socket(uid: String, websocket: warp::filters::ws::WebSocket) {
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub extern "C" fn command(command: &str) {
+    if !points::command(command) {
+        log::error!("{command}: not a valid place command!");
+    }
+}
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub extern "C" fn language(language: &str) {
+    i18n::set_stream_language(language);
+}
+
+async fn socket(uid: String, _: Option<std::net::IpAddr>, websocket: warp::filters::ws::WebSocket) {
     use futures_util::{SinkExt, StreamExt};
     let (mut tx, mut rx) = websocket.split();
     let palette = [
@@ -221,6 +237,7 @@ async fn socket(uid: String, websocket: warp::filters::ws::WebSocket) {
                                 if allowed {
                                     STATE.lock().unwrap().as_mut().unwrap().image[(x, y)] =
                                         Pixel::new(color, Some(uid.clone()));
+                                    points::achievements::report(&uid, "place", "pixels", 1);
                                 }
                             }
                         }
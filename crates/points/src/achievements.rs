@@ -0,0 +1,312 @@
+use crate::*;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+use warp::Filter;
+
+/// Used when there is no achievements file
+const DEFAULT_ACHIEVEMENTS: &str = r#"
+[[achievement]]
+id = "tetro-first-win"
+name.en = "First victory"
+name.ru = "Первая победа"
+game = "tetro"
+event = "win"
+threshold = 1
+bonus = 10
+
+[[achievement]]
+id = "tetro-zone-8"
+name.en = "In the zone"
+name.ru = "В зоне"
+game = "tetro"
+event = "zone_lines"
+threshold = 8
+bonus = 20
+
+[[achievement]]
+id = "tttoe-10-lines"
+name.en = "Line master"
+name.ru = "Мастер линий"
+game = "tttoe"
+event = "lines"
+threshold = 10
+bonus = 20
+
+[[achievement]]
+id = "place-1000-pixels"
+name.en = "Pixel artist"
+name.ru = "Пиксельный художник"
+game = "place"
+event = "pixels"
+threshold = 1000
+cumulative = true
+bonus = 50
+"#;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Achievement {
+    pub id: String,
+    pub name: i18n::Localized,
+    pub game: String,
+    /// Game event this achievement listens to
+    pub event: String,
+    /// Value the event has to reach at once, or summed up over all games if `cumulative`
+    pub threshold: u64,
+    #[serde(default)]
+    pub cumulative: bool,
+    /// Points given on top when the achievement is unlocked
+    #[serde(default)]
+    pub bonus: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Award {
+    pub uid: String,
+    pub achievement: String,
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+}
+
+#[derive(Deserialize)]
+struct AchievementsFile {
+    #[serde(default, rename = "achievement")]
+    achievements: Vec<Achievement>,
+}
+
+fn parse(source: &str) -> Option<Vec<Achievement>> {
+    let file: AchievementsFile = try_log!(
+        "Failed to parse achievements: {}!";
+        toml::from_str(source)
+        => None
+    );
+    Some(file.achievements)
+}
+
+pub fn achievements() -> &'static [Achievement] {
+    static ACHIEVEMENTS: std::sync::OnceLock<Vec<Achievement>> = std::sync::OnceLock::new();
+    ACHIEVEMENTS.get_or_init(|| {
+        let path = config()
+            .points
+            .achievements
+            .as_deref()
+            .unwrap_or("achievements.toml");
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|source| parse(&source))
+            .or_else(|| parse(DEFAULT_ACHIEVEMENTS))
+            .unwrap_or_default()
+    })
+}
+
+// * ----------------------------------- Events ----------------------------------- * //
+/// Cumulative events are summed up and stored once this much is pending
+const FLUSH_AMOUNT: u64 = 50;
+/// ..or once the first pending event is this old
+const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Pending values of cumulative events by uid, game and event
+type Pending = HashMap<(String, String, String), u64>;
+
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+
+/// Achievements every seen user already has, so events after unlocking don't touch the store
+static AWARDED: Mutex<Option<HashMap<String, HashSet<String>>>> = Mutex::new(None);
+
+async fn awarded(uid: &str) -> HashSet<String> {
+    let cached = AWARDED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .get(uid)
+        .cloned();
+    if let Some(awarded) = cached {
+        return awarded;
+    }
+    let awarded = store::store()
+        .await
+        .awards(Some(uid))
+        .await
+        .into_iter()
+        .map(|award| award.achievement)
+        .collect::<HashSet<_>>();
+    AWARDED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .entry(uid.to_owned())
+        .or_default()
+        .extend(awarded.iter().cloned());
+    awarded
+}
+
+fn mark_awarded(uid: &str, achievement: &str) {
+    AWARDED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .entry(uid.to_owned())
+        .or_default()
+        .insert(achievement.to_owned());
+}
+
+/// Feed a game event to the achievements engine. Unlocked achievements are stored,
+/// give their bonus and are announced on stream
+pub async fn event(uid: &str, game: &str, event: &str, value: u64) {
+    check(uid, game, event, value, false).await;
+    check(uid, game, event, value, true).await;
+}
+
+/// Check only the cumulative achievements of an event or only the others
+async fn check(uid: &str, game: &str, event: &str, value: u64, cumulative: bool) {
+    let awarded = awarded(uid).await;
+    let achievements = achievements()
+        .iter()
+        .filter(|achievement| {
+            achievement.game == game
+                && achievement.event == event
+                && achievement.cumulative == cumulative
+                && !awarded.contains(&achievement.id)
+        })
+        .collect::<Vec<_>>();
+    if achievements.is_empty() {
+        return;
+    }
+
+    let store = store::store().await;
+    let progress = if cumulative {
+        try_map!(
+            store
+                .add_progress(uid, &format!("{game}:{event}"), value)
+                .await,
+            Some
+        )
+    } else {
+        value
+    };
+    for achievement in achievements {
+        if progress < achievement.threshold {
+            continue;
+        }
+
+        let Some(award) = store
+            .award(Award {
+                uid: uid.to_owned(),
+                achievement: achievement.id.clone(),
+                timestamp: chrono::Utc::now().timestamp(),
+            })
+            .await
+        else {
+            continue;
+        };
+        mark_awarded(uid, &achievement.id);
+        log::info!("{} unlocked {}", award.uid, award.achievement);
+
+        if achievement.bonus > 0 {
+            give(uid, achievement.bonus, "achievements", &achievement.id).await;
+        }
+
+        let user = get_user_profile(uid)
+            .await
+            .and_then(|user| user.display_name)
+            .unwrap_or("Someone".to_owned());
+        announce(Announcement {
+            text: i18n::Localized::PerLanguage(
+                i18n::languages()
+                    .map(|language| {
+                        (
+                            language.to_owned(),
+                            tr(
+                                language,
+                                "achievement-unlocked",
+                                &[
+                                    ("user", &user),
+                                    ("achievement", &achievement.name.get(language)),
+                                ],
+                            ),
+                        )
+                    })
+                    .collect(),
+            ),
+            priority: 1,
+            duration: 8.0,
+            style: AnnouncementStyle {
+                color: None,
+                icon: Some("🏆".to_owned()),
+            },
+        });
+    }
+}
+
+/// Report a game event from a frame, AI players are ignored.
+/// Cumulative events are batched, so reporting every small step is cheap
+pub fn report(uid: &str, game: &str, name: &str, value: u64) {
+    if uid == "AI" {
+        return;
+    }
+    let (uid, game, name) = (uid.to_owned(), game.to_owned(), name.to_owned());
+    let (mut at_once, mut cumulative) = (false, false);
+    for achievement in achievements()
+        .iter()
+        .filter(|achievement| achievement.game == game && achievement.event == name)
+    {
+        at_once |= !achievement.cumulative;
+        cumulative |= achievement.cumulative;
+    }
+
+    if at_once {
+        let (uid, game, name) = (uid.clone(), game.clone(), name.clone());
+        spawn_in_server_runtime(async move { check(&uid, &game, &name, value, false).await });
+    }
+    if !cumulative {
+        return;
+    }
+
+    let key = (uid, game, name);
+    let (first, flush) = {
+        let mut pending = PENDING.lock().unwrap();
+        let pending = pending.get_or_insert_with(Pending::new);
+        let first = !pending.contains_key(&key);
+        let total = pending.entry(key.clone()).or_default();
+        *total += value;
+        let flush = (*total >= FLUSH_AMOUNT)
+            .then(|| pending.remove(&key))
+            .flatten();
+        (first, flush)
+    };
+    if flush.is_none() && !first {
+        return;
+    }
+    spawn_in_server_runtime(async move {
+        let value = match flush {
+            Some(value) => value,
+            None => {
+                tokio::time::sleep(FLUSH_INTERVAL).await;
+                let value = PENDING
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(Pending::new)
+                    .remove(&key);
+                try_map!(value, Some)
+            }
+        };
+        let (uid, game, name) = key;
+        check(&uid, &game, &name, value, true).await;
+    });
+}
+
+/// Routes under `/leaderboard/api/achievements`
+pub fn routes(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + Send + Sync + 'static
+{
+    let all = warp::path::end().map(|| warp::reply::json(&achievements()));
+    let user =
+        warp::path::param::<String>()
+            .and(warp::path::end())
+            .then(|uid: String| async move {
+                let awards = store::store().await.awards(Some(&uid)).await;
+                warp::reply::json(&awards)
+            });
+    warp::path("achievements").and(all.or(user).unify())
+}
@@ -0,0 +1,507 @@
+use crate::*;
+use std::time::{Instant, SystemTime};
+
+/// Used when there is no banner file
+const DEFAULT_MESSAGES: &str = r#"
+[[message]]
+text.en = "Time left to the next event: {time_left}"
+text.ru = "До следующего события: {time_left}"
+duration = 20
+last_event = false
+
+[[message]]
+text.en = "Time left to the end: {time_left}"
+text.ru = "До конца: {time_left}"
+duration = 20
+last_event = true
+
+[[message]]
+text.en = "Current leader: {leader}"
+text.ru = "Текущий лидер: {leader}"
+
+[[message]]
+text.en = "Try it yourself at event.infinitecoder.org (Link in description)"
+text.ru = "Попробуйте сами на event.infinitecoder.org (ссылка в описании)"
+
+[[message]]
+text.en = "Follow me on Telegram: https://t.me/InfiniteCoder02"
+text.ru = "Подписывайтесь на Telegram: https://t.me/InfiniteCoder02"
+
+[[message]]
+text.en = "If you like the event, subscribe and press the like button!"
+text.ru = "Если вам нравится событие, подпишитесь и поставьте лайк!"
+
+[[message]]
+text.en = "If you like this event and want to see more, you can support me on Patreon or via StreamElements (Links in description)"
+text.ru = "Если вам нравится событие и хочется ещё, поддержите меня на Patreon или через StreamElements (ссылки в описании)"
+"#;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BannerMessage {
+    /// `{time_left}`, `{leader}`, `{queue_length}` and `{game}` are filled in.
+    /// Either a string or a table of languages
+    pub text: i18n::Localized,
+    /// Seconds on screen
+    #[serde(default = "default_duration")]
+    pub duration: f64,
+    /// How often the message is shown relative to the others
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Only shown inside this time window
+    pub start: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub end: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// Only shown during the last scheduled event (or all others, if false)
+    pub last_event: Option<bool>,
+}
+
+fn default_duration() -> f64 {
+    10.0
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl BannerMessage {
+    fn is_active(&self, last_event: bool) -> bool {
+        let now = chrono::Utc::now();
+        self.weight > 0
+            && self.last_event.map_or(true, |only| only == last_event)
+            && self.start.map_or(true, |start| now >= start)
+            && self.end.map_or(true, |end| now < end)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AnnouncementStyle {
+    /// Frame and text color instead of the usual ones
+    pub color: Option<[f64; 3]>,
+    /// Shown before the text, like an emoji
+    pub icon: Option<String>,
+}
+
+impl AnnouncementStyle {
+    pub fn alert() -> Self {
+        Self {
+            color: Some(theme().color("alert").into()),
+            icon: Some("⚠".to_owned()),
+        }
+    }
+}
+
+/// Shown instead of the usual messages until its duration runs out
+#[derive(Clone, Debug, Deserialize)]
+pub struct Announcement {
+    /// Filled in like banner messages
+    pub text: i18n::Localized,
+    /// Higher priority announcements interrupt lower ones, the rest wait in line
+    #[serde(default)]
+    pub priority: i32,
+    /// Seconds on screen
+    #[serde(default = "default_duration")]
+    pub duration: f64,
+    #[serde(default)]
+    pub style: AnnouncementStyle,
+}
+
+#[derive(Debug, Deserialize)]
+struct BannerFile {
+    #[serde(default, rename = "message")]
+    messages: Vec<BannerMessage>,
+}
+
+impl BannerFile {
+    fn parse(source: &str) -> Option<Vec<BannerMessage>> {
+        let file: Self = try_log!(
+            "Failed to parse banner messages: {}!";
+            toml::from_str(source)
+            => None
+        );
+        Some(file.messages)
+    }
+}
+
+pub(crate) struct Banner {
+    messages: Vec<BannerMessage>,
+    /// Smooth weighted round robin counters
+    weights: Vec<i64>,
+    current: Option<usize>,
+    switch_time: Instant,
+    modified: Option<SystemTime>,
+    last_check: Option<Instant>,
+    events: tokio::sync::broadcast::Receiver<LeaderboardEvent>,
+    /// Long messages scroll instead of overflowing
+    marquee: Marquee,
+    last_frame: Instant,
+    announcement: Option<(Announcement, Instant)>,
+    /// Waiting announcements, highest priority first
+    announcements: Vec<Announcement>,
+}
+
+impl Banner {
+    pub(crate) fn new() -> Self {
+        let messages = BannerFile::parse(DEFAULT_MESSAGES).unwrap_or_default();
+        Self {
+            weights: vec![0; messages.len()],
+            messages,
+            current: None,
+            switch_time: Instant::now(),
+            modified: None,
+            last_check: None,
+            marquee: Marquee::default(),
+            last_frame: Instant::now(),
+            events: events::subscribe(),
+            announcement: None,
+            announcements: Vec::new(),
+        }
+    }
+
+    /// Pick up changes to the banner file, checked every couple of seconds
+    fn reload(&mut self) {
+        if self
+            .last_check
+            .is_some_and(|time| time.elapsed() < std::time::Duration::from_secs(2))
+        {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+
+        let path = &config().banner.messages;
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        let messages = match modified {
+            Some(_) => try_map!(
+                BannerFile::parse(&try_log!(
+                    "Failed to load banner messages {}: {}!", path;
+                    std::fs::read_to_string(path)
+                )),
+                Some
+            ),
+            None => BannerFile::parse(DEFAULT_MESSAGES).unwrap_or_default(),
+        };
+        log::info!("Loaded {} banner messages", messages.len());
+        self.weights = vec![0; messages.len()];
+        self.messages = messages;
+        self.current = None;
+    }
+
+    fn next(&mut self, last_event: bool) {
+        self.switch_time = Instant::now();
+        let active = (0..self.messages.len())
+            .filter(|&index| self.messages[index].is_active(last_event))
+            .collect::<Vec<_>>();
+        let total = active
+            .iter()
+            .map(|&index| self.messages[index].weight as i64)
+            .sum::<i64>();
+        for &index in &active {
+            self.weights[index] += self.messages[index].weight as i64;
+        }
+        self.current = active
+            .iter()
+            .copied()
+            .rev()
+            .max_by_key(|&index| self.weights[index]);
+        if let Some(index) = self.current {
+            self.weights[index] -= total;
+        }
+    }
+
+    fn announce(&mut self, announcement: Announcement) {
+        if self
+            .announcement
+            .as_ref()
+            .is_some_and(|(current, _)| announcement.priority > current.priority)
+        {
+            // * The interrupted one continues later with the time it had left
+            let (mut current, shown) = self.announcement.take().unwrap();
+            current.duration -= shown.elapsed().as_secs_f64();
+            self.queue_announcement(current, true);
+        }
+        self.queue_announcement(announcement, false);
+    }
+
+    fn queue_announcement(&mut self, announcement: Announcement, first: bool) {
+        let index = self
+            .announcements
+            .iter()
+            .position(|other| {
+                other.priority < announcement.priority
+                    || first && other.priority == announcement.priority
+            })
+            .unwrap_or(self.announcements.len());
+        self.announcements.insert(index, announcement);
+    }
+
+    fn update(&mut self, last_event: bool) {
+        self.reload();
+
+        if self
+            .announcement
+            .as_ref()
+            .is_some_and(|(announcement, shown)| {
+                shown.elapsed().as_secs_f64() >= announcement.duration
+            })
+        {
+            self.announcement = None;
+            self.current = None;
+        }
+        if self.announcement.is_none() && !self.announcements.is_empty() {
+            self.announcement = Some((self.announcements.remove(0), Instant::now()));
+        }
+
+        // * Show a new leader right away
+        while let Ok(event) = self.events.try_recv() {
+            if let LeaderboardEvent::NewLeader { .. } = event {
+                if let Some(index) = self.messages.iter().position(|message| {
+                    message.text.contains("{leader}") && message.is_active(last_event)
+                }) {
+                    self.current = Some(index);
+                    self.switch_time = Instant::now();
+                }
+            }
+        }
+
+        let expired = self.current.map_or(true, |index| {
+            let message = &self.messages[index];
+            !message.is_active(last_event)
+                || self.switch_time.elapsed().as_secs_f64() >= message.duration
+        });
+        if expired && self.announcement.is_none() {
+            self.next(last_event);
+        }
+    }
+}
+
+/// Show an announcement on the banner, returns false if there is no banner
+pub fn announce(announcement: Announcement) -> bool {
+    let mut state = STATE.lock().unwrap();
+    let Some(state) = state.as_mut() else {
+        log::error!("No banner to show the announcement on!");
+        return false;
+    };
+    log::info!(
+        "Announcement (priority {}, {}s): {:?}",
+        announcement.priority,
+        announcement.duration,
+        announcement.text
+    );
+    state.banner.announce(announcement);
+    true
+}
+
+/// Remove the current and all waiting announcements
+pub fn clear_announcements() {
+    if let Some(state) = STATE.lock().unwrap().as_mut() {
+        state.banner.announcement = None;
+        state.banner.announcements.clear();
+        state.banner.current = None;
+    }
+}
+
+async fn is_staff(uid: &str) -> bool {
+    store::store()
+        .await
+        .user(uid)
+        .await
+        .is_some_and(|user| user.roles.contains(&Role::Staff))
+}
+
+/// `POST /leaderboard/api/announcements` with a staff member's ID token as bearer token
+pub fn routes() -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection>
+       + Clone
+       + Send
+       + Sync
+       + 'static {
+    use warp::Filter;
+
+    warp::path("announcements")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::header::<String>("authorization"))
+        .and(warp::body::json())
+        .then(
+            |authorization: String, announcement: Announcement| async move {
+                let uid = match authorization.strip_prefix("Bearer ") {
+                    Some(token) => auth::verify_id_token(token).await,
+                    None => None,
+                };
+                let status = match uid {
+                    Some(uid) if is_staff(&uid).await => {
+                        if announce(announcement) {
+                            warp::http::StatusCode::OK
+                        } else {
+                            warp::http::StatusCode::SERVICE_UNAVAILABLE
+                        }
+                    }
+                    Some(_) => warp::http::StatusCode::FORBIDDEN,
+                    None => warp::http::StatusCode::UNAUTHORIZED,
+                };
+                warp::reply::with_status(warp::reply(), status)
+            },
+        )
+}
+
+/// Replace `{key}` placeholders in one pass, so values can't inject placeholders of their own
+fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(key, _)| *key == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                text.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+pub fn make_bottom_banner(
+    context: &cairo::Context,
+    width: f64,
+    height: f64,
+    time_left: Duration,
+    last_event: bool,
+    game: &str,
+    queue_length: Option<usize>,
+) -> f64 {
+    let padding = 10.0;
+    let radius = 10.0;
+    let banner_height = (height / 20.0).floor() + padding * 2.0 + radius * 2.0;
+    let y = height - banner_height;
+
+    // * Frame
+    rounded_rectangle(
+        context,
+        padding,
+        y + padding,
+        width - padding * 2.0,
+        banner_height - padding * 2.0,
+        radius,
+    );
+
+    let mut state = STATE.lock().unwrap();
+    let state = state.as_mut().unwrap();
+    state.banner.update(last_event);
+    let style = state
+        .banner
+        .announcement
+        .as_ref()
+        .map(|(announcement, _)| announcement.style.clone())
+        .unwrap_or_default();
+    let border = style
+        .color
+        .map_or(theme().color("banner_border"), Color::from);
+
+    theme().set_color(context, "banner_background");
+    log_error!("{}"; context.fill_preserve());
+    context.set_source_rgb(border.0, border.1, border.2);
+    context.set_line_width(2.0);
+    log_error!("{}"; context.stroke());
+
+    // * Message
+    let language = i18n::stream_language();
+    let template = match (&state.banner.announcement, state.banner.current) {
+        (Some((announcement, _)), _) => announcement.text.get(&language),
+        (None, Some(index)) => state.banner.messages[index].text.get(&language),
+        (None, None) => return banner_height,
+    };
+
+    let time_left = {
+        use hhmmss::Hhmmss;
+
+        let days = time_left.num_days();
+        let hhmmss = time_left - Duration::days(days);
+        if days > 0 {
+            tr(
+                &language,
+                "time-left-days",
+                &[("days", &days), ("time", &hhmmss.hhmmss())],
+            )
+        } else {
+            hhmmss.hhmmss()
+        }
+    };
+    let text = render(
+        template,
+        &[
+            ("time_left", &time_left),
+            (
+                "leader",
+                &leader(state).map_or(tr(&language, "banner-nobody", &[]), |(_, name, _)| {
+                    name.to_owned()
+                }),
+            ),
+            ("queue_length", &queue_length.unwrap_or(0).to_string()),
+            ("game", game),
+        ],
+    );
+
+    let text = match &style.icon {
+        Some(icon) => format!("{icon} {text}"),
+        None => text,
+    };
+    let font_size = banner_height - padding * 2.0 - radius * 2.0;
+    let text_width = width - (padding + radius) * 2.0;
+    let text_height = banner_height - padding * 2.0 - radius;
+    let center_y = y + (banner_height / 2.0).floor();
+    match style.color {
+        Some([r, g, b]) => context.set_source_rgb(r, g, b),
+        None => theme().set_color(context, "banner_text"),
+    }
+
+    let frame_time = state.banner.last_frame.elapsed().as_secs_f64();
+    state.banner.last_frame = Instant::now();
+    // * Long messages wrap onto a second line, messages too long for that scroll instead
+    if fit_font_size(
+        context,
+        &text,
+        font_size,
+        (font_size * 0.4).floor(),
+        text_width,
+        text_height,
+    ) {
+        show_lines(
+            context,
+            &wrap_text(context, &text, text_width),
+            padding + radius,
+            center_y,
+            false,
+        );
+    } else {
+        context.set_font_size((font_size * 0.6).floor());
+        if let Some(offset) = text_center_offset(context, &text) {
+            state.banner.marquee.draw(
+                context,
+                &text,
+                padding + radius,
+                center_y - offset.y,
+                text_width,
+                frame_time,
+            );
+        }
+    }
+
+    banner_height
+}
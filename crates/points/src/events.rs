@@ -0,0 +1,60 @@
+use crate::*;
+use tokio::sync::broadcast;
+use warp::Filter;
+
+/// Leaderboard changes, pushed as soon as points are committed
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LeaderboardEvent {
+    Points {
+        name: String,
+        game: String,
+        amount: i64,
+        points: u64,
+        rank: Option<usize>,
+        previous_rank: Option<usize>,
+    },
+    NewLeader {
+        name: String,
+        points: u64,
+    },
+}
+
+static EVENTS: std::sync::OnceLock<broadcast::Sender<LeaderboardEvent>> =
+    std::sync::OnceLock::new();
+
+fn sender() -> &'static broadcast::Sender<LeaderboardEvent> {
+    EVENTS.get_or_init(|| broadcast::channel(64).0)
+}
+
+pub fn subscribe() -> broadcast::Receiver<LeaderboardEvent> {
+    sender().subscribe()
+}
+
+pub(crate) fn send(event: LeaderboardEvent) {
+    // * Nobody listening is not an error
+    sender().send(event).ok();
+}
+
+/// Server-sent events at `/leaderboard/api/events`
+pub fn routes(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + Send + Sync + 'static
+{
+    warp::path("events").and(warp::path::end()).map(|| {
+        let events = futures_util::stream::unfold(subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        let event = warp::sse::Event::default()
+                            .json_data(&event)
+                            .unwrap_or_default();
+                        return Some((Ok::<_, std::convert::Infallible>(event), events));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        warp::sse::reply(warp::sse::keep_alive().stream(events))
+    })
+}
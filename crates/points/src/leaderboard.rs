@@ -0,0 +1,247 @@
+use crate::*;
+use std::collections::HashMap;
+use warp::Filter;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LeaderboardQuery {
+    /// Only count points from this game
+    pub game: Option<String>,
+    /// Only count points from this season
+    pub season: Option<String>,
+    /// Unix timestamps (in seconds) of the counted time range
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+    /// Neighbors on each side for the "around me" query
+    pub radius: Option<usize>,
+}
+
+impl LeaderboardQuery {
+    /// Only windowed and seasonal queries need the ledger, the rest are served from balances
+    fn is_windowed(&self) -> bool {
+        self.season.is_some() || self.from.is_some() || self.to.is_some()
+    }
+
+    fn matches(&self, entry: &LedgerEntry) -> bool {
+        // * Resets are made when a season closes, so they would land in the next season
+        if entry.game == seasons::RESET_GAME {
+            return false;
+        }
+        if self.game.as_ref().is_some_and(|game| game != &entry.game) {
+            return false;
+        }
+        if let Some(season) = &self.season {
+            match config()
+                .points
+                .seasons
+                .iter()
+                .find(|other| &other.id == season)
+            {
+                Some(season) => {
+                    if season
+                        .start
+                        .is_some_and(|start| entry.timestamp < start.timestamp())
+                        || season
+                            .end
+                            .is_some_and(|end| entry.timestamp >= end.timestamp())
+                    {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+        !(self.from.is_some_and(|from| entry.timestamp < from)
+            || self.to.is_some_and(|to| entry.timestamp >= to))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LeaderboardItem {
+    pub rank: usize,
+    pub name: String,
+    pub points: u64,
+    pub highlighted: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AroundMe {
+    /// None if the user has no points in this leaderboard
+    pub rank: Option<usize>,
+    pub total: usize,
+    pub entries: Vec<LeaderboardItem>,
+}
+
+/// Points of every user by game, kept up to date next to the balances
+pub type GameTotals = HashMap<String, HashMap<String, i64>>;
+
+pub fn game_totals(ledger: &[LedgerEntry]) -> GameTotals {
+    let mut games = GameTotals::new();
+    for entry in ledger {
+        *games
+            .entry(entry.game.clone())
+            .or_default()
+            .entry(entry.uid.clone())
+            .or_default() += entry.amount;
+    }
+    games
+}
+
+/// Full ranked leaderboard for the query, ignoring pagination
+pub fn rank(
+    leaderboard: &[(String, String, UserData)],
+    ledger: &[LedgerEntry],
+    games: &GameTotals,
+    query: &LeaderboardQuery,
+    uid: Option<&str>,
+) -> Vec<LeaderboardItem> {
+    rank_users(leaderboard, ledger, games, query)
+        .into_iter()
+        .map(|(user_uid, item)| LeaderboardItem {
+            highlighted: Some(user_uid) == uid,
+            ..item
+        })
+        .collect()
+}
+
+/// Same as [`rank`], along with the uid of every item
+pub fn rank_users<'a>(
+    leaderboard: &'a [(String, String, UserData)],
+    ledger: &'a [LedgerEntry],
+    games: &'a GameTotals,
+    query: &LeaderboardQuery,
+) -> Vec<(&'a str, LeaderboardItem)> {
+    let points = if query.is_windowed() {
+        let mut points = HashMap::<&str, i64>::new();
+        for entry in ledger.iter().filter(|entry| query.matches(entry)) {
+            *points.entry(&entry.uid).or_default() += entry.amount;
+        }
+        Some(points)
+    } else {
+        query.game.as_ref().map(|game| {
+            games.get(game).map_or(HashMap::new(), |points| {
+                points
+                    .iter()
+                    .map(|(uid, &points)| (uid.as_str(), points))
+                    .collect()
+            })
+        })
+    };
+
+    let mut users = if let Some(points) = points {
+        let users = leaderboard
+            .iter()
+            .map(|(uid, name, user)| (uid.as_str(), (name.as_str(), user)))
+            .collect::<HashMap<_, _>>();
+        let no_data = UserData::default();
+
+        let mut ranked = points
+            .into_iter()
+            .filter(|&(_, points)| points > 0)
+            .map(|(uid, points)| {
+                let (name, user) = users.get(uid).copied().unwrap_or(("Someone", &no_data));
+                (uid, name, user, points as u64)
+            })
+            .filter(|(_, _, user, _)| !user.is_excluded())
+            .collect::<Vec<_>>();
+        ranked.sort_by_key(|&(_, _, user, points)| sort_key(user, points));
+        ranked
+            .into_iter()
+            .map(|(uid, name, _, points)| (uid, name, points))
+            .collect::<Vec<_>>()
+    } else {
+        leaderboard
+            .iter()
+            .filter(|(_, _, user)| !user.is_excluded())
+            .map(|(uid, name, user)| (uid.as_str(), name.as_str(), user.points))
+            .collect()
+    };
+
+    // * Users with equal points share a rank
+    let mut ranked = Vec::with_capacity(users.len());
+    for (index, (user_uid, name, points)) in users.drain(..).enumerate() {
+        let rank = match ranked.last() {
+            Some((
+                _,
+                LeaderboardItem {
+                    rank,
+                    points: last_points,
+                    ..
+                },
+            )) if *last_points == points => *rank,
+            _ => index + 1,
+        };
+        ranked.push((
+            user_uid,
+            LeaderboardItem {
+                rank,
+                name: name.to_owned(),
+                points,
+                highlighted: false,
+            },
+        ));
+    }
+    ranked
+}
+
+fn with_state<T>(f: impl FnOnce(&State) -> T) -> T {
+    let state = STATE.lock().unwrap();
+    f(state.as_ref().unwrap())
+}
+
+pub fn page(uid: Option<&str>, query: &LeaderboardQuery) -> Vec<LeaderboardItem> {
+    with_state(|state| {
+        rank(&state.leaderboard, &state.ledger, &state.games, query, uid)
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect()
+    })
+}
+
+pub fn around(uid: &str, query: &LeaderboardQuery) -> AroundMe {
+    let radius = query.radius.unwrap_or(5);
+    with_state(|state| {
+        let leaderboard = rank(
+            &state.leaderboard,
+            &state.ledger,
+            &state.games,
+            query,
+            Some(uid),
+        );
+        let position = leaderboard.iter().position(|item| item.highlighted);
+        AroundMe {
+            rank: position.map(|position| leaderboard[position].rank),
+            total: leaderboard.len(),
+            entries: position.map_or(Vec::new(), |position| {
+                leaderboard[position.saturating_sub(radius)
+                    ..(position + radius + 1).min(leaderboard.len())]
+                    .to_vec()
+            }),
+        }
+    })
+}
+
+/// Routes under `/leaderboard/api`
+pub fn routes(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + Send + Sync + 'static
+{
+    let query = warp::query::<LeaderboardQuery>();
+
+    let around = warp::path::param::<String>()
+        .and(warp::path("around"))
+        .and(warp::path::end())
+        .and(query.clone())
+        .map(|uid: String, query| warp::reply::json(&around(&uid, &query)));
+    let user = warp::path::param::<String>()
+        .and(warp::path::end())
+        .and(query.clone())
+        .map(|uid: String, query| warp::reply::json(&page(Some(&uid), &query)));
+    let all = warp::path::end()
+        .and(query)
+        .map(|query| warp::reply::json(&page(None, &query)));
+
+    around.or(user).unify().or(all).unify()
+}
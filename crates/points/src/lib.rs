@@ -1,268 +1,497 @@
-use std::sync::Mutex;
+pub mod achievements;
+pub mod banner;
+pub mod events;
+pub mod leaderboard;
+pub mod seasons;
+pub mod store;
 
-use firestore::FirestoreDb;
-use scheduler::*;
+pub use banner::{announce, make_bottom_banner, Announcement, AnnouncementStyle};
+pub use events::LeaderboardEvent;
+pub use leaderboard::{LeaderboardItem, LeaderboardQuery};
+
+use scheduler::{config::Role, *};
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
 
 // * ----------------------------------- API stuff ---------------------------------- * //
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UserData {
-    points: u64,
+    pub points: u64,
+    pub roles: BTreeSet<Role>,
+}
+
+impl UserData {
+    /// Not shown on the leaderboard at all
+    pub fn is_excluded(&self) -> bool {
+        config()
+            .leaderboard
+            .exclude
+            .iter()
+            .any(|role| self.roles.contains(role))
+    }
+
+    /// Shown below everyone else and never the leader
+    pub fn is_demoted(&self) -> bool {
+        config()
+            .leaderboard
+            .demote
+            .iter()
+            .any(|role| self.roles.contains(role))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub id: String,
+    pub uid: String,
+    pub amount: i64,
+    pub game: String,
+    pub match_id: String,
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+    /// Id of the entry this one cancels out
+    pub reverses: Option<String>,
+}
+
+impl std::fmt::Display for LedgerEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{}] {:+} to {} for {} ({})",
+            self.id,
+            chrono::DateTime::from_timestamp(self.timestamp, 0)
+                .map_or(self.timestamp.to_string(), |time| time.to_rfc3339()),
+            self.amount,
+            self.uid,
+            self.game,
+            self.match_id,
+        )?;
+        if let Some(reverses) = &self.reverses {
+            write!(f, ", reverses {reverses}")?;
+        }
+        Ok(())
+    }
 }
 
-pub async fn get_firestore() -> Option<FirestoreDb> {
-    log_error!(
-        "Failed to connect to firestore: {}!";
-        FirestoreDb::with_options_service_account_key_file(
-                firestore::FirestoreDbOptions::new("infinitecoderwebsite".to_owned()),
-                "firebase-private.json".into()
-        ).await
+/// Unique, roughly time ordered id for ledger entries and matches
+pub fn new_id() -> String {
+    static COUNTER: std::sync::atomic::AtomicU16 = std::sync::atomic::AtomicU16::new(0);
+    format!(
+        "{:013x}{:04x}",
+        chrono::Utc::now().timestamp_micros(),
+        COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
     )
 }
 
-pub async fn give(uid: &str, amount: u64) {
-    let firestore = try_map!(get_firestore().await, Some);
-    if let Some(mut user) = try_log!(
-        "Failed to get user points from firestore: {}!";
-        firestore
-            .fluent()
-            .select()
-            .by_id_in("users")
-            .obj::<UserData>()
-            .one(uid)
-            .await
-    ) {
-        user.points += amount;
-        try_log!(
-            "Failed to update user points: {}!";
-            firestore.fluent()
-                .update()
-                .fields(["points"])
-                .in_col("users")
-                .document_id(uid)
-                .object(&user)
-                .execute()
-                .await
-        );
-    } else {
-        try_log!(
-            "Failed to create user in firestore: {}!";
-            firestore
-                .fluent()
-                .insert()
-                .into("users")
-                .document_id(uid)
-                .object(&UserData {
-                    points: amount,
-                })
-                .execute()
-                .await
-        );
+pub fn new_match_id() -> String {
+    new_id()
+}
+
+pub async fn give(uid: &str, amount: u64, game: &str, match_id: &str) -> Option<LedgerEntry> {
+    append(uid, amount as _, game, match_id).await
+}
+
+async fn append(uid: &str, amount: i64, game: &str, match_id: &str) -> Option<LedgerEntry> {
+    let entry = store::store()
+        .await
+        .append(LedgerEntry {
+            id: new_id(),
+            uid: uid.to_owned(),
+            amount,
+            game: game.to_owned(),
+            match_id: match_id.to_owned(),
+            timestamp: chrono::Utc::now().timestamp(),
+            reverses: None,
+        })
+        .await?;
+    apply_entries(vec![entry.clone()], entry.timestamp).await;
+    Some(entry)
+}
+
+pub async fn ledger(uid: Option<&str>) -> Vec<LedgerEntry> {
+    store::store().await.ledger(uid).await
+}
+
+/// Cancel out a ledger entry by appending the opposite one
+pub async fn reverse(id: &str) -> Option<LedgerEntry> {
+    let entry = store::store().await.reverse(id).await?;
+    apply_entries(vec![entry.clone()], entry.timestamp).await;
+    Some(entry)
+}
+
+/// Admin commands, returns false if the command is not a points command
+pub fn command(command: &str) -> bool {
+    let (cmd, args) = command.split_once(' ').unwrap_or((command, ""));
+    let args = args.trim().to_owned();
+    match cmd {
+        "ledger" => {
+            spawn_in_server_runtime(async move {
+                let ledger = ledger((!args.is_empty()).then_some(args.as_str())).await;
+                for entry in &ledger {
+                    log::info!("{entry}");
+                }
+                log::info!("{} ledger entries", ledger.len());
+            });
+        }
+        "reverse" => {
+            spawn_in_server_runtime(async move {
+                if let Some(entry) = reverse(&args).await {
+                    log::info!("Reversed: {entry}");
+                }
+            });
+        }
+        "roles" => {
+            let (uid, changes) = args.split_once(' ').unwrap_or((&args, ""));
+            let (uid, changes) = (uid.to_owned(), changes.to_owned());
+            spawn_in_server_runtime(async move {
+                let mut roles = store::store()
+                    .await
+                    .user(&uid)
+                    .await
+                    .unwrap_or_default()
+                    .roles;
+
+                for change in changes.split_whitespace() {
+                    let (add, role) = if let Some(role) = change.strip_prefix('-') {
+                        (false, role)
+                    } else {
+                        (true, change.strip_prefix('+').unwrap_or(change))
+                    };
+                    let role = try_log!("{}"; role.parse::<Role>());
+                    if add {
+                        roles.insert(role);
+                    } else {
+                        roles.remove(&role);
+                    }
+                }
+
+                if !changes.is_empty() {
+                    try_map!(set_roles(&uid, roles.clone()).await, Some);
+                }
+                log::info!("Roles of {uid}: {roles:?}");
+            });
+        }
+        "season" => {
+            spawn_in_server_runtime(async move {
+                match args.split_once(' ') {
+                    Some(("close", id)) => {
+                        seasons::close(id.trim()).await;
+                    }
+                    _ => {
+                        for season in seasons::seasons().await {
+                            log::info!("{season:?}");
+                        }
+                    }
+                }
+            });
+        }
+        "announce" | "alert" if args == "clear" => banner::clear_announcements(),
+        "announce" | "alert" => {
+            let mut parts = args.splitn(3, ' ');
+            let (Some(Ok(priority)), Some(Ok(duration)), Some(text)) = (
+                parts.next().map(str::parse),
+                parts.next().map(str::parse),
+                parts.next(),
+            ) else {
+                log::error!("Usage: {cmd} <priority> <seconds> <text> or {cmd} clear");
+                return true;
+            };
+            announce(Announcement {
+                text: i18n::Localized::Any(text.to_owned()),
+                priority,
+                duration,
+                style: if cmd == "alert" {
+                    AnnouncementStyle::alert()
+                } else {
+                    AnnouncementStyle::default()
+                },
+            });
+        }
+        _ => return false,
     }
+    true
 }
 
 pub async fn get_leaderboard() -> Vec<(String, String, UserData)> {
-    use futures_util::StreamExt;
-    let firestore = try_map!(get_firestore().await, Some => Vec::new());
-
-    let mut users = try_log!(
-        "Failed to get users from firestore: {}!";
-        firestore
-            .fluent()
-            .list()
-            .from("users")
-            .stream_all()
-            .await
-        => Vec::new()
-    );
-
-    let mut leaderboard = Vec::new();
-    while let Some(doc) = users.next().await {
-        let user = try_log!(
-            "Failed to deserialize user data: {}!";
-            FirestoreDb::deserialize_doc_to::<UserData>(&doc)
-            => Vec::new()
-        );
-        let uid = doc.name.split('/').last().unwrap();
-        leaderboard.push((
-            uid.to_owned(),
-            get_firebase_user(uid.to_owned())
-                .await
-                .and_then(|user| user.display_name)
-                .unwrap_or("Someone".to_owned()),
-            user,
-        ));
-    }
+    let users = store::store().await.users().await;
+    let profiles =
+        get_user_profiles(&users.iter().map(|(uid, _)| uid.clone()).collect::<Vec<_>>()).await;
 
-    leaderboard.sort_by_key(|(uid, _, user)| {
-        if uid == "GiAIWs311JaKAWwTEkll5LLPKT63" {
-            1
-        } else {
-            -(user.points as i64)
-        }
-    });
+    let mut leaderboard = users
+        .into_iter()
+        .map(|(uid, user)| {
+            let name = display_name(&profiles, &uid);
+            (uid, name, user)
+        })
+        .collect::<Vec<_>>();
+    leaderboard.sort_by_key(|(_, _, user)| sort_key(user, user.points));
     leaderboard
 }
 
-// * ------------------------------------- State ------------------------------------ * //
-#[derive(serde::Serialize)]
-pub struct LeaderboardItem {
-    pub name: String,
-    pub points: u64,
-    pub highlighted: bool,
+/// Set everyone's points to the sum of their ledger entries
+fn sum_ledger_points(leaderboard: &mut [(String, String, UserData)], ledger: &[LedgerEntry]) {
+    let mut points = HashMap::<&str, i64>::new();
+    for entry in ledger {
+        *points.entry(&entry.uid).or_default() += entry.amount;
+    }
+    for (uid, _, user) in leaderboard.iter_mut() {
+        user.points = points.get(uid.as_str()).copied().unwrap_or_default().max(0) as u64;
+    }
+    leaderboard.sort_by_key(|(_, _, user)| sort_key(user, user.points));
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum BannerMessage {
-    TimeLeft,
-    CurrentLeader,
-    TryYourself,
-    TelegramAd,
-    YoutubeAd,
-    SupportDonate,
+fn display_name(profiles: &HashMap<String, UserProfile>, uid: &str) -> String {
+    profiles
+        .get(uid)
+        .and_then(|user| user.display_name.clone())
+        .unwrap_or("Someone".to_owned())
+}
+
+fn sort_key(user: &UserData, points: u64) -> (bool, std::cmp::Reverse<u64>) {
+    (user.is_demoted(), std::cmp::Reverse(points))
+}
+
+pub async fn set_roles(uid: &str, roles: BTreeSet<Role>) -> Option<()> {
+    store::store().await.set_roles(uid, roles.clone()).await?;
+
+    // * Apply right away instead of waiting for the next refresh
+    let mut state = STATE.lock().unwrap();
+    if let Some(state) = state.as_mut() {
+        if let Some((_, _, user)) = state
+            .leaderboard
+            .iter_mut()
+            .find(|(other, _, _)| other == uid)
+        {
+            user.roles = roles;
+        }
+        state
+            .leaderboard
+            .sort_by_key(|(_, _, user)| sort_key(user, user.points));
+    }
+    Some(())
+}
+
+/// Give the staff role to the uids configured in `leaderboard.staff`
+async fn seed_staff() {
+    let store = store::store().await;
+    for uid in &config().leaderboard.staff {
+        let mut roles = store.user(uid).await.unwrap_or_default().roles;
+        if roles.insert(Role::Staff) && set_roles(uid, roles).await.is_some() {
+            log::info!("Gave {uid} the staff role");
+        }
+    }
 }
 
+// * ------------------------------------- State ------------------------------------ * //
 struct State {
     leaderboard: Vec<(String, String, UserData)>,
-    banner_switch_time: std::time::Instant,
-    banner_message: BannerMessage,
+    ledger: Vec<LedgerEntry>,
+    games: leaderboard::GameTotals,
+    banner: banner::Banner,
 }
 
 static STATE: Mutex<Option<State>> = Mutex::new(None);
 
+/// Uid, name and points of the first user that can be the leader
+fn leader(state: &State) -> Option<(&str, &str, u64)> {
+    state
+        .leaderboard
+        .iter()
+        .find(|(_, _, user)| !user.is_excluded() && !user.is_demoted())
+        .map(|(uid, name, user)| (uid.as_str(), name.as_str(), user.points))
+}
+
+/// Overall rank of every shown user
+fn ranks(state: &State) -> HashMap<String, usize> {
+    leaderboard::rank_users(
+        &state.leaderboard,
+        &[],
+        &state.games,
+        &LeaderboardQuery::default(),
+    )
+    .into_iter()
+    .map(|(uid, item)| (uid.to_owned(), item.rank))
+    .collect()
+}
+
 // * ------------------------------------ Server ------------------------------------ * //
-pub fn make_leaderboard_server() -> &'static impl Fn(Option<String>) -> warp::reply::Json {
-    {
-        *STATE.lock().unwrap() = Some(State {
-            leaderboard: Vec::new(),
-            banner_switch_time: std::time::Instant::now(),
-            banner_message: BannerMessage::TimeLeft,
-        })
-    }
+const FULL_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
 
-    spawn_in_server_runtime(async {
-        loop {
-            let new_leaderboard = get_leaderboard().await;
-            if !new_leaderboard.is_empty() {
-                let mut state = STATE.lock().unwrap();
-                let state = state.as_mut().unwrap();
-                state.leaderboard = new_leaderboard;
-            }
-            tokio::time::sleep(tokio::time::Duration::from_secs(20)).await;
-        }
-    });
+/// Fetch only the ledger entries added since the last refresh and apply them to the cached
+/// leaderboard. Returns the timestamp to continue from
+async fn apply_new_entries(since: i64) -> i64 {
+    let entries = store::store().await.ledger_since(since).await;
+    apply_entries(entries, since).await
+}
+
+/// Apply committed ledger entries to the cached leaderboard and notify subscribers.
+/// Entries that were already applied are skipped
+async fn apply_entries(entries: Vec<LedgerEntry>, since: i64) -> i64 {
+    if entries.is_empty() {
+        return since;
+    }
 
-    &move |uid| {
+    let new_uids = {
         let state = STATE.lock().unwrap();
-        let state = state.as_ref().unwrap();
-        let leaderboard = state
-            .leaderboard
+        let Some(state) = state.as_ref() else {
+            return since;
+        };
+        let mut new_uids = entries
             .iter()
-            .map(|(leader_uid, name, user)| LeaderboardItem {
-                name: name.clone(),
-                points: user.points,
-                highlighted: Some(leader_uid) == uid.as_ref(),
+            .filter(|entry| {
+                !state
+                    .leaderboard
+                    .iter()
+                    .any(|(uid, _, _)| uid == &entry.uid)
             })
+            .map(|entry| entry.uid.clone())
             .collect::<Vec<_>>();
-        warp::reply::json(&leaderboard)
-    }
-}
+        new_uids.sort();
+        new_uids.dedup();
+        new_uids
+    };
+    let profiles = get_user_profiles(&new_uids).await;
 
-// * ------------------------------------ Banner ------------------------------------ * //
-pub fn make_bottom_banner(
-    context: &cairo::Context,
-    width: f64,
-    height: f64,
-    time_left: Duration,
-    last_event: bool,
-) -> f64 {
-    let padding = 10.0;
-    let radius = 10.0;
-    let banner_height = (height / 20.0).floor() + padding * 2.0 + radius * 2.0;
-    let y = height - banner_height;
-
-    // * Frame
-    rounded_rectangle(
-        context,
-        padding,
-        y + padding,
-        width - padding * 2.0,
-        banner_height - padding * 2.0,
-        radius,
-    );
-
-    context.set_source_rgb(0.1, 0.1, 0.1);
-    log_error!("{}"; context.fill_preserve());
-    context.set_source_rgb(0.25, 0.6, 0.66);
-    context.set_line_width(2.0);
-    log_error!("{}"; context.stroke());
-
-    // * Message
     let mut state = STATE.lock().unwrap();
-    let state = state.as_mut().unwrap();
+    let Some(state) = state.as_mut() else {
+        return since;
+    };
+    let previous_leader = leader(state).map(|(uid, _, _)| uid.to_owned());
+    let previous_ranks = ranks(state);
+    let mut applied = Vec::new();
+    for entry in entries {
+        // * Entries from the last second of the previous refresh are fetched again
+        if state
+            .ledger
+            .iter()
+            .rev()
+            .take_while(|known| known.timestamp >= since.min(entry.timestamp))
+            .any(|known| known.id == entry.id)
+        {
+            continue;
+        }
 
-    {
-        use std::time::Duration;
-        if state.banner_switch_time.elapsed()
-            > match state.banner_message {
-                BannerMessage::TimeLeft => Duration::from_secs(20),
-                _ => Duration::from_secs(10),
-            }
+        let index = match state
+            .leaderboard
+            .iter()
+            .position(|(uid, _, _)| uid == &entry.uid)
         {
-            state.banner_switch_time = std::time::Instant::now();
-            state.banner_message = match state.banner_message {
-                BannerMessage::TimeLeft => BannerMessage::CurrentLeader,
-                BannerMessage::CurrentLeader => BannerMessage::TryYourself,
-                BannerMessage::TryYourself => BannerMessage::TelegramAd,
-                BannerMessage::TelegramAd => BannerMessage::YoutubeAd,
-                BannerMessage::YoutubeAd => BannerMessage::SupportDonate,
-                BannerMessage::SupportDonate => BannerMessage::TimeLeft,
+            Some(index) => index,
+            None => {
+                let name = display_name(&profiles, &entry.uid);
+                state
+                    .leaderboard
+                    .push((entry.uid.clone(), name, UserData::default()));
+                state.leaderboard.len() - 1
             }
-        }
+        };
+        let user = &mut state.leaderboard[index].2;
+        user.points = user.points.saturating_add_signed(entry.amount);
+        *state
+            .games
+            .entry(entry.game.clone())
+            .or_default()
+            .entry(entry.uid.clone())
+            .or_default() += entry.amount;
+        applied.push(entry.clone());
+        state.ledger.push(entry);
     }
+    state
+        .leaderboard
+        .sort_by_key(|(_, _, user)| sort_key(user, user.points));
 
-    context.set_font_size(banner_height - padding * 2.0 - radius * 2.0);
-    let message = match state.banner_message {
-        BannerMessage::TimeLeft => {
-            use hhmmss::Hhmmss;
-
-            let days = time_left.num_days();
-            let hhmmss = time_left - Duration::days(days);
-            let time_left = if days > 0 {
-                format!("{} days and {}", days, hhmmss.hhmmss())
-            } else {
-                hhmmss.hhmmss()
-            };
-            if last_event {
-                format!("Time left to the end: {time_left}")
-            } else {
-                format!("Time left to the next event: {time_left}")
+    let new_ranks = ranks(state);
+    for entry in applied {
+        if let Some((_, name, user)) = state
+            .leaderboard
+            .iter()
+            .find(|(uid, _, _)| uid == &entry.uid)
+        {
+            if user.is_excluded() {
+                continue;
             }
+            events::send(LeaderboardEvent::Points {
+                name: name.clone(),
+                game: entry.game.clone(),
+                amount: entry.amount,
+                points: user.points,
+                rank: new_ranks.get(&entry.uid).copied(),
+                previous_rank: previous_ranks.get(&entry.uid).copied(),
+            });
         }
-        BannerMessage::CurrentLeader => format!("Current leader: {}", state.leaderboard[0].1),
-        BannerMessage::TryYourself => {
-            "Try it yourself at event.infinitecoder.org (Link in description)".to_owned()
-        }
-        BannerMessage::TelegramAd => {
-            "Follow me on Telegram: https://t.me/InfiniteCoder02".to_owned()
-        }
-        BannerMessage::YoutubeAd => {
-            "If you like the event, subscribe and press the like button!".to_owned()
-        }
-        BannerMessage::SupportDonate => {
-            context.set_font_size(((banner_height - padding * 2.0 - radius * 2.0) * 0.6).floor());
-            "If you like this event and want to see more, you can support me on Patreon or via StreamElements (Links in description)".to_owned()
+    }
+    if let Some((uid, name, points)) = leader(state) {
+        if previous_leader.as_deref() != Some(uid) {
+            events::send(LeaderboardEvent::NewLeader {
+                name: name.to_owned(),
+                points,
+            });
         }
-    };
+    }
+
+    state
+        .ledger
+        .last()
+        .map_or(since, |entry| entry.timestamp.max(since))
+}
 
-    if let Some(offset) = text_center_offset(context, &message) {
-        context.set_source_rgb(1.0, 1.0, 1.0);
-        context.move_to(
-            padding + radius,
-            y + (banner_height / 2.0).floor() - offset.y,
-        );
-        log_error!("{}"; context.show_text(&message));
+pub fn make_leaderboard_server(
+) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection>
+       + Clone
+       + Send
+       + Sync
+       + 'static {
+    {
+        *STATE.lock().unwrap() = Some(State {
+            leaderboard: Vec::new(),
+            ledger: Vec::new(),
+            games: leaderboard::GameTotals::new(),
+            banner: banner::Banner::new(),
+        })
     }
 
-    banner_height
+    spawn_in_server_runtime(async {
+        seed_staff().await;
+        let mut since = 0;
+        let mut last_full_refresh: Option<std::time::Instant> = None;
+        loop {
+            // * Everything is reloaded once in a while to pick up names and roles
+            if last_full_refresh.map_or(true, |time| time.elapsed() > FULL_REFRESH_INTERVAL) {
+                // * Retry on the normal schedule even if the store failed, and don't hammer it
+                last_full_refresh = Some(std::time::Instant::now());
+                let fetch_started = chrono::Utc::now().timestamp();
+                // * Points are summed from this ledger snapshot rather than read with the users,
+                // * so entries given in between are neither missed nor counted twice
+                let new_ledger = ledger(None).await;
+                let mut new_leaderboard = get_leaderboard().await;
+                if !new_leaderboard.is_empty() {
+                    sum_ledger_points(&mut new_leaderboard, &new_ledger);
+                    {
+                        let mut state = STATE.lock().unwrap();
+                        let state = state.as_mut().unwrap();
+                        state.leaderboard = new_leaderboard;
+                        state.games = leaderboard::game_totals(&new_ledger);
+                        state.ledger = new_ledger;
+                    }
+                    // * Entries given while fetching were applied to the replaced state,
+                    // * so the ones the fetch missed are applied again below
+                    since = fetch_started;
+                }
+            }
+            since = apply_new_entries(since).await;
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        }
+    });
+
+    use warp::Filter;
+    events::routes()
+        .or(banner::routes())
+        .or(achievements::routes())
+        .or(seasons::routes())
+        // * Last, its `/<uid>` route would take the paths above
+        .or(leaderboard::routes())
 }
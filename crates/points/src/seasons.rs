@@ -0,0 +1,197 @@
+use crate::*;
+use warp::Filter;
+
+/// Game of the ledger entries that reset balances when a season is closed
+pub const RESET_GAME: &str = "season";
+
+/// Final standings of a closed season
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonArchive {
+    pub id: String,
+    /// Unix timestamp in seconds
+    pub closed: i64,
+    pub standings: Vec<Standing>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Standing {
+    pub uid: String,
+    pub rank: usize,
+    pub name: String,
+    pub points: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SeasonInfo {
+    pub id: String,
+    /// Unix timestamps in seconds
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub closed: Option<i64>,
+}
+
+/// Reset balances keeping the `carry_over` share of a configured season, then archive its standings
+pub async fn close(id: &str) -> Option<SeasonArchive> {
+    let season = try_map!(
+        config().points.seasons.iter().find(|season| season.id == id),
+        Some => {
+            log::error!("No season {id:?} in the config!");
+            None
+        }
+    );
+    let store = store::store().await;
+    if !store.archives(Some(id)).await.is_empty() {
+        log::error!("Season {id:?} is already closed!");
+        return None;
+    }
+
+    let leaderboard = get_leaderboard().await;
+    let ledger = ledger(None).await;
+    let query = LeaderboardQuery {
+        season: Some(id.to_owned()),
+        ..Default::default()
+    };
+    let standings = leaderboard::rank_users(&leaderboard, &ledger, &Default::default(), &query)
+        .into_iter()
+        .map(|(uid, item)| Standing {
+            uid: uid.to_owned(),
+            rank: item.rank,
+            name: item.name,
+            points: item.points,
+        })
+        .collect();
+
+    // * Balances are only changed through the ledger, so the reset shows up there too.
+    // * Reset ids are derived from the season, so closing again after a failure resets nobody twice
+    let carry_over = season.carry_over.clamp(0.0, 1.0);
+    let mut resets = Vec::new();
+    let mut failed = false;
+    for (uid, _) in store.users().await {
+        let reset_id = format!("{id}-reset-{uid}");
+        if store.entry(&reset_id).await.is_some() {
+            continue;
+        }
+        // * Read right before appending, so points given in the meantime are reset as well
+        let points = store.user(&uid).await.unwrap_or_default().points;
+        let kept = (points as f64 * carry_over).round() as u64;
+        if kept >= points {
+            continue;
+        }
+        match store
+            .append(LedgerEntry {
+                id: reset_id,
+                uid: uid.clone(),
+                amount: kept as i64 - points as i64,
+                game: RESET_GAME.to_owned(),
+                match_id: id.to_owned(),
+                timestamp: chrono::Utc::now().timestamp(),
+                reverses: None,
+            })
+            .await
+        {
+            Some(entry) => resets.push(entry),
+            None => {
+                log::error!("Failed to reset points of {uid} for season {id:?}!");
+                failed = true;
+            }
+        }
+    }
+    if let Some(timestamp) = resets.iter().map(|entry| entry.timestamp).min() {
+        apply_entries(resets, timestamp).await;
+    }
+    if failed {
+        log::error!("Season {id:?} stays open, close it again to retry the failed resets!");
+        return None;
+    }
+
+    // * The archive marks the season as closed, so it is written once everything else is done
+    let archive = try_map!(
+        store
+            .archive(SeasonArchive {
+                id: id.to_owned(),
+                closed: chrono::Utc::now().timestamp(),
+                standings,
+            })
+            .await,
+        Some => {
+            log::error!("Failed to archive season {id:?}!");
+            None
+        }
+    );
+
+    log::info!(
+        "Closed season {id:?} with {} players",
+        archive.standings.len()
+    );
+    Some(archive)
+}
+
+pub async fn seasons() -> Vec<SeasonInfo> {
+    let archives = store::store().await.archives(None).await;
+    let mut seasons = config()
+        .points
+        .seasons
+        .iter()
+        .map(|season| SeasonInfo {
+            id: season.id.clone(),
+            start: season.start.map(|start| start.timestamp()),
+            end: season.end.map(|end| end.timestamp()),
+            closed: archives
+                .iter()
+                .find(|archive| archive.id == season.id)
+                .map(|archive| archive.closed),
+        })
+        .collect::<Vec<_>>();
+
+    // * Seasons removed from the config are still served from the archive
+    for archive in &archives {
+        if !seasons.iter().any(|season| season.id == archive.id) {
+            seasons.push(SeasonInfo {
+                id: archive.id.clone(),
+                start: None,
+                end: None,
+                closed: Some(archive.closed),
+            });
+        }
+    }
+    seasons
+}
+
+/// Archived standings of a closed season, or the live ones of a running season
+pub async fn standings(id: &str, query: &LeaderboardQuery) -> Vec<LeaderboardItem> {
+    match store::store().await.archives(Some(id)).await.pop() {
+        Some(archive) => archive
+            .standings
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|standing| LeaderboardItem {
+                rank: standing.rank,
+                name: standing.name,
+                points: standing.points,
+                highlighted: false,
+            })
+            .collect(),
+        None => leaderboard::page(
+            None,
+            &LeaderboardQuery {
+                season: Some(id.to_owned()),
+                ..query.clone()
+            },
+        ),
+    }
+}
+
+/// Routes under `/leaderboard/api/seasons`
+pub fn routes(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + Send + Sync + 'static
+{
+    let all = warp::path::end().then(|| async { warp::reply::json(&seasons().await) });
+    let season = warp::path::param::<String>()
+        .and(warp::path::end())
+        .and(warp::query::<LeaderboardQuery>())
+        .then(|id: String, query: LeaderboardQuery| async move {
+            warp::reply::json(&standings(&id, &query).await)
+        });
+    warp::path("seasons").and(all.or(season).unify())
+}
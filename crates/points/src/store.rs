@@ -0,0 +1,1176 @@
+use crate::{achievements::Award, seasons::SeasonArchive, LedgerEntry, UserData};
+use firestore::FirestoreDb;
+use scheduler::{
+    config::{PointsBackend, Role},
+    *,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// Storage for user points. Points are only ever changed by appending ledger entries,
+/// which are applied to the user's balance in the same transaction
+pub trait PointsStore: Send + Sync {
+    /// Fails if an entry with the same id exists. Balances don't go below zero, so the
+    /// returned entry has the amount that was actually applied
+    fn append(&self, entry: LedgerEntry) -> BoxFuture<'_, Option<LedgerEntry>>;
+    fn entry(&self, id: &str) -> BoxFuture<'_, Option<LedgerEntry>>;
+    /// All ledger entries, or only the entries of one user, oldest first
+    fn ledger(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<LedgerEntry>>;
+    /// Ledger entries with a timestamp of at least `timestamp`, oldest first
+    fn ledger_since(&self, timestamp: i64) -> BoxFuture<'_, Vec<LedgerEntry>>;
+    fn users(&self) -> BoxFuture<'_, Vec<(String, UserData)>>;
+    /// None if the user has no points or roles yet
+    fn user(&self, uid: &str) -> BoxFuture<'_, Option<UserData>>;
+    fn set_roles(&self, uid: &str, roles: BTreeSet<Role>) -> BoxFuture<'_, Option<()>>;
+    /// All awarded achievements, or only the ones of one user
+    fn awards(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<Award>>;
+    /// Returns None if the user already has this achievement
+    fn award(&self, award: Award) -> BoxFuture<'_, Option<Award>>;
+    /// Add to a user's achievement progress counter, returns the new value
+    fn add_progress(&self, uid: &str, key: &str, amount: u64) -> BoxFuture<'_, Option<u64>>;
+    /// All closed seasons, or only the one with this id, oldest first
+    fn archives(&self, id: Option<&str>) -> BoxFuture<'_, Vec<SeasonArchive>>;
+    /// Returns None if the season is already archived
+    fn archive(&self, archive: SeasonArchive) -> BoxFuture<'_, Option<SeasonArchive>>;
+
+    /// Cancel out a ledger entry by appending the opposite one
+    fn reverse<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Option<LedgerEntry>> {
+        Box::pin(async move {
+            let entry = try_map!(self.entry(id).await, Some => {
+                log::error!("Ledger entry {id:?} does not exist!");
+                None
+            });
+            if entry.reverses.is_some() {
+                log::error!("Ledger entry {id:?} is a reversal itself!");
+                return None;
+            }
+
+            // * The reversal id is derived from the entry, so the store rejects a second reversal
+            // * even if both are made at the same time
+            let reversal_id = format!("{id}-reversal");
+            if self.entry(&reversal_id).await.is_some() {
+                log::error!("Ledger entry {id:?} is already reversed!");
+                return None;
+            }
+
+            self.append(LedgerEntry {
+                id: reversal_id,
+                amount: -entry.amount,
+                timestamp: chrono::Utc::now().timestamp(),
+                reverses: Some(entry.id.clone()),
+                ..entry
+            })
+            .await
+        })
+    }
+}
+
+// * ----------------------------------- Firestore ---------------------------------- * //
+pub struct FirestoreStore {
+    db: FirestoreDb,
+}
+
+impl FirestoreStore {
+    pub async fn connect() -> Option<Self> {
+        Some(Self {
+            db: try_log!(
+                "Failed to connect to firestore: {}!";
+                FirestoreDb::with_options_service_account_key_file(
+                    firestore::FirestoreDbOptions::new(config().firebase.project_id.clone()),
+                    config().firebase.credentials.clone().into(),
+                ).await
+                => None
+            ),
+        })
+    }
+}
+
+impl PointsStore for FirestoreStore {
+    fn append(&self, mut entry: LedgerEntry) -> BoxFuture<'_, Option<LedgerEntry>> {
+        Box::pin(async move {
+            let mut transaction = try_log!(
+                "Failed to begin points transaction: {}!";
+                self.db.begin_transaction().await
+                => None
+            );
+            let user = try_log!(
+                "Failed to get user points from firestore: {}!";
+                self.db
+                    .clone_with_consistency_selector(
+                        firestore::FirestoreConsistencySelector::Transaction(
+                            transaction.transaction_id.clone(),
+                        ),
+                    )
+                    .fluent()
+                    .select()
+                    .by_id_in("users")
+                    .obj::<UserData>()
+                    .one(&entry.uid)
+                    .await
+                => None
+            );
+
+            let mut user = user.unwrap_or_default();
+            let previous = user.points;
+            user.points = user.points.saturating_add_signed(entry.amount);
+            entry.amount = user.points as i64 - previous as i64;
+            try_log!(
+                "Failed to update user points: {}!";
+                self.db
+                    .fluent()
+                    .update()
+                    .in_col("users")
+                    .document_id(&entry.uid)
+                    .object(&user)
+                    .add_to_transaction(&mut transaction)
+                => None
+            );
+            try_log!(
+                "Failed to append ledger entry: {}!";
+                self.db
+                    .fluent()
+                    .update()
+                    .in_col("ledger")
+                    .precondition(firestore::FirestoreWritePrecondition::Exists(false))
+                    .document_id(&entry.id)
+                    .object(&entry)
+                    .add_to_transaction(&mut transaction)
+                => None
+            );
+            try_log!(
+                "Failed to commit points transaction: {}!";
+                transaction.commit().await
+                => None
+            );
+            Some(entry)
+        })
+    }
+
+    fn entry(&self, id: &str) -> BoxFuture<'_, Option<LedgerEntry>> {
+        let id = id.to_owned();
+        Box::pin(async move {
+            try_log!(
+                "Failed to get ledger entry from firestore: {}!";
+                self.db
+                    .fluent()
+                    .select()
+                    .by_id_in("ledger")
+                    .obj::<LedgerEntry>()
+                    .one(&id)
+                    .await
+                => None
+            )
+        })
+    }
+
+    fn ledger(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<LedgerEntry>> {
+        let uid = uid.map(str::to_owned);
+        Box::pin(async move {
+            let query = self.db.fluent().select().from("ledger");
+            let mut ledger = try_log!(
+                "Failed to get ledger from firestore: {}!";
+                if let Some(uid) = uid {
+                    query
+                        .filter(|q| q.field("uid").eq(uid.clone()))
+                        .obj::<LedgerEntry>()
+                        .query()
+                        .await
+                } else {
+                    query.obj::<LedgerEntry>().query().await
+                }
+                => Vec::new()
+            );
+            ledger.sort_by_key(|entry| entry.timestamp);
+            ledger
+        })
+    }
+
+    fn ledger_since(&self, timestamp: i64) -> BoxFuture<'_, Vec<LedgerEntry>> {
+        Box::pin(async move {
+            let mut ledger = try_log!(
+                "Failed to get ledger from firestore: {}!";
+                self.db
+                    .fluent()
+                    .select()
+                    .from("ledger")
+                    .filter(|q| q.field("timestamp").greater_than_or_equal(timestamp))
+                    .obj::<LedgerEntry>()
+                    .query()
+                    .await
+                => Vec::new()
+            );
+            ledger.sort_by_key(|entry| entry.timestamp);
+            ledger
+        })
+    }
+
+    fn users(&self) -> BoxFuture<'_, Vec<(String, UserData)>> {
+        Box::pin(async {
+            use futures_util::StreamExt;
+            let mut docs = try_log!(
+                "Failed to get users from firestore: {}!";
+                self.db
+                    .fluent()
+                    .list()
+                    .from("users")
+                    .stream_all()
+                    .await
+                => Vec::new()
+            );
+
+            let mut users = Vec::new();
+            while let Some(doc) = docs.next().await {
+                let user = try_log!(
+                    "Failed to deserialize user data: {}!";
+                    FirestoreDb::deserialize_doc_to::<UserData>(&doc)
+                    => Vec::new()
+                );
+                users.push((doc.name.split('/').last().unwrap().to_owned(), user));
+            }
+            users
+        })
+    }
+
+    fn user(&self, uid: &str) -> BoxFuture<'_, Option<UserData>> {
+        let uid = uid.to_owned();
+        Box::pin(async move {
+            try_log!(
+                "Failed to get user from firestore: {}!";
+                self.db
+                    .fluent()
+                    .select()
+                    .by_id_in("users")
+                    .obj::<UserData>()
+                    .one(&uid)
+                    .await
+                => None
+            )
+        })
+    }
+
+    fn set_roles(&self, uid: &str, roles: BTreeSet<Role>) -> BoxFuture<'_, Option<()>> {
+        let uid = uid.to_owned();
+        Box::pin(async move {
+            try_log!(
+                "Failed to update user roles: {}!";
+                self.db
+                    .fluent()
+                    .update()
+                    .fields(["roles"])
+                    .in_col("users")
+                    .document_id(&uid)
+                    .object(&UserData {
+                        roles,
+                        ..Default::default()
+                    })
+                    .execute()
+                    .await
+                => None
+            );
+            Some(())
+        })
+    }
+
+    fn awards(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<Award>> {
+        let uid = uid.map(str::to_owned);
+        Box::pin(async move {
+            let query = self.db.fluent().select().from("achievements");
+            let mut awards = try_log!(
+                "Failed to get achievements from firestore: {}!";
+                if let Some(uid) = uid {
+                    query
+                        .filter(|q| q.field("uid").eq(uid.clone()))
+                        .obj::<Award>()
+                        .query()
+                        .await
+                } else {
+                    query.obj::<Award>().query().await
+                }
+                => Vec::new()
+            );
+            awards.sort_by_key(|award| award.timestamp);
+            awards
+        })
+    }
+
+    fn award(&self, award: Award) -> BoxFuture<'_, Option<Award>> {
+        Box::pin(async move {
+            let id = format!("{}:{}", award.uid, award.achievement);
+            let mut transaction = try_log!(
+                "Failed to begin achievement transaction: {}!";
+                self.db.begin_transaction().await
+                => None
+            );
+            let existing = try_log!(
+                "Failed to get achievement from firestore: {}!";
+                self.db
+                    .clone_with_consistency_selector(
+                        firestore::FirestoreConsistencySelector::Transaction(
+                            transaction.transaction_id.clone(),
+                        ),
+                    )
+                    .fluent()
+                    .select()
+                    .by_id_in("achievements")
+                    .obj::<Award>()
+                    .one(&id)
+                    .await
+                => None
+            );
+            if existing.is_some() {
+                log_error!("Failed to roll back achievement transaction: {}!"; transaction.rollback().await);
+                return None;
+            }
+            try_log!(
+                "Failed to award achievement: {}!";
+                self.db
+                    .fluent()
+                    .update()
+                    .in_col("achievements")
+                    .document_id(&id)
+                    .object(&award)
+                    .add_to_transaction(&mut transaction)
+                => None
+            );
+            try_log!(
+                "Failed to commit achievement transaction: {}!";
+                transaction.commit().await
+                => None
+            );
+            Some(award)
+        })
+    }
+
+    fn add_progress(&self, uid: &str, key: &str, amount: u64) -> BoxFuture<'_, Option<u64>> {
+        #[derive(Default, serde::Serialize, serde::Deserialize)]
+        struct Progress {
+            value: u64,
+        }
+
+        let id = format!("{uid}:{key}");
+        Box::pin(async move {
+            let mut transaction = try_log!(
+                "Failed to begin progress transaction: {}!";
+                self.db.begin_transaction().await
+                => None
+            );
+            let progress = try_log!(
+                "Failed to get achievement progress from firestore: {}!";
+                self.db
+                    .clone_with_consistency_selector(
+                        firestore::FirestoreConsistencySelector::Transaction(
+                            transaction.transaction_id.clone(),
+                        ),
+                    )
+                    .fluent()
+                    .select()
+                    .by_id_in("progress")
+                    .obj::<Progress>()
+                    .one(&id)
+                    .await
+                => None
+            );
+            let mut progress = progress.unwrap_or_default();
+            progress.value = progress.value.saturating_add(amount);
+            try_log!(
+                "Failed to update achievement progress: {}!";
+                self.db
+                    .fluent()
+                    .update()
+                    .in_col("progress")
+                    .document_id(&id)
+                    .object(&progress)
+                    .add_to_transaction(&mut transaction)
+                => None
+            );
+            try_log!(
+                "Failed to commit progress transaction: {}!";
+                transaction.commit().await
+                => None
+            );
+            Some(progress.value)
+        })
+    }
+
+    fn archives(&self, id: Option<&str>) -> BoxFuture<'_, Vec<SeasonArchive>> {
+        let id = id.map(str::to_owned);
+        Box::pin(async move {
+            let query = self.db.fluent().select().from("seasons");
+            let mut archives = try_log!(
+                "Failed to get seasons from firestore: {}!";
+                if let Some(id) = id {
+                    query
+                        .filter(|q| q.field("id").eq(id.clone()))
+                        .obj::<SeasonArchive>()
+                        .query()
+                        .await
+                } else {
+                    query.obj::<SeasonArchive>().query().await
+                }
+                => Vec::new()
+            );
+            archives.sort_by_key(|archive| archive.closed);
+            archives
+        })
+    }
+
+    fn archive(&self, archive: SeasonArchive) -> BoxFuture<'_, Option<SeasonArchive>> {
+        Box::pin(async move {
+            let mut transaction = try_log!(
+                "Failed to begin season transaction: {}!";
+                self.db.begin_transaction().await
+                => None
+            );
+            let existing = try_log!(
+                "Failed to get season from firestore: {}!";
+                self.db
+                    .clone_with_consistency_selector(
+                        firestore::FirestoreConsistencySelector::Transaction(
+                            transaction.transaction_id.clone(),
+                        ),
+                    )
+                    .fluent()
+                    .select()
+                    .by_id_in("seasons")
+                    .obj::<SeasonArchive>()
+                    .one(&archive.id)
+                    .await
+                => None
+            );
+            if existing.is_some() {
+                log_error!("Failed to roll back season transaction: {}!"; transaction.rollback().await);
+                return None;
+            }
+            try_log!(
+                "Failed to archive season: {}!";
+                self.db
+                    .fluent()
+                    .update()
+                    .in_col("seasons")
+                    .document_id(&archive.id)
+                    .object(&archive)
+                    .add_to_transaction(&mut transaction)
+                => None
+            );
+            try_log!(
+                "Failed to commit season transaction: {}!";
+                transaction.commit().await
+                => None
+            );
+            Some(archive)
+        })
+    }
+}
+
+// * ------------------------------------ SQLite ------------------------------------ * //
+pub struct SqliteStore {
+    connection: Mutex<rusqlite::Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<std::path::Path>) -> Option<Self> {
+        let path = path.as_ref();
+        let connection = try_log!(
+            "Failed to open points database {}: {}!", path.display();
+            rusqlite::Connection::open(path)
+            => None
+        );
+        try_log!(
+            "Failed to create points tables: {}!";
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS users (
+                    uid TEXT PRIMARY KEY,
+                    points INTEGER NOT NULL,
+                    roles TEXT NOT NULL DEFAULT '[]'
+                );
+                CREATE TABLE IF NOT EXISTS ledger (
+                    id TEXT PRIMARY KEY,
+                    uid TEXT NOT NULL,
+                    amount INTEGER NOT NULL,
+                    game TEXT NOT NULL,
+                    match_id TEXT NOT NULL,
+                    timestamp INTEGER NOT NULL,
+                    reverses TEXT
+                );
+                CREATE INDEX IF NOT EXISTS ledger_uid ON ledger (uid);
+                CREATE TABLE IF NOT EXISTS achievements (
+                    uid TEXT NOT NULL,
+                    achievement TEXT NOT NULL,
+                    timestamp INTEGER NOT NULL,
+                    PRIMARY KEY (uid, achievement)
+                );
+                CREATE TABLE IF NOT EXISTS progress (
+                    uid TEXT NOT NULL,
+                    key TEXT NOT NULL,
+                    value INTEGER NOT NULL,
+                    PRIMARY KEY (uid, key)
+                );
+                CREATE TABLE IF NOT EXISTS seasons (
+                    id TEXT PRIMARY KEY,
+                    closed INTEGER NOT NULL,
+                    standings TEXT NOT NULL
+                );",
+            )
+            => None
+        );
+        Some(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn read_entry(row: &rusqlite::Row) -> rusqlite::Result<LedgerEntry> {
+        Ok(LedgerEntry {
+            id: row.get(0)?,
+            uid: row.get(1)?,
+            amount: row.get(2)?,
+            game: row.get(3)?,
+            match_id: row.get(4)?,
+            timestamp: row.get(5)?,
+            reverses: row.get(6)?,
+        })
+    }
+}
+
+const LEDGER_COLUMNS: &str = "id, uid, amount, game, match_id, timestamp, reverses";
+
+impl PointsStore for SqliteStore {
+    fn append(&self, mut entry: LedgerEntry) -> BoxFuture<'_, Option<LedgerEntry>> {
+        let result = (|| {
+            use rusqlite::OptionalExtension;
+            let mut connection = self.connection.lock().unwrap();
+            let transaction = connection.transaction()?;
+            let points: i64 = transaction
+                .query_row(
+                    "SELECT points FROM users WHERE uid = ?1",
+                    [&entry.uid],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or_default();
+            entry.amount = entry.amount.max(-points);
+            transaction.execute(
+                &format!(
+                    "INSERT INTO ledger ({LEDGER_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+                ),
+                (
+                    &entry.id,
+                    &entry.uid,
+                    entry.amount,
+                    &entry.game,
+                    &entry.match_id,
+                    entry.timestamp,
+                    &entry.reverses,
+                ),
+            )?;
+            transaction.execute(
+                "INSERT INTO users (uid, points) VALUES (?1, ?2)
+                    ON CONFLICT (uid) DO UPDATE SET points = points + ?2",
+                (&entry.uid, entry.amount),
+            )?;
+            transaction.commit()
+        })();
+        let entry = log_error!("Failed to append ledger entry: {}!"; result).map(|_| entry);
+        Box::pin(async { entry })
+    }
+
+    fn entry(&self, id: &str) -> BoxFuture<'_, Option<LedgerEntry>> {
+        let entry = {
+            use rusqlite::OptionalExtension;
+            let connection = self.connection.lock().unwrap();
+            log_error!(
+                "Failed to get ledger entry: {}!";
+                connection
+                    .query_row(
+                        &format!("SELECT {LEDGER_COLUMNS} FROM ledger WHERE id = ?1"),
+                        [id],
+                        Self::read_entry,
+                    )
+                    .optional()
+            )
+            .flatten()
+        };
+        Box::pin(async { entry })
+    }
+
+    fn ledger(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<LedgerEntry>> {
+        let ledger = (|| {
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection.prepare(&format!(
+                "SELECT {LEDGER_COLUMNS} FROM ledger WHERE ?1 IS NULL OR uid = ?1 ORDER BY timestamp"
+            ))?;
+            let ledger = statement
+                .query_map([uid], Self::read_entry)?
+                .collect::<Result<Vec<_>, _>>();
+            ledger
+        })();
+        let ledger =
+            log_error!("Failed to get ledger from database: {}!"; ledger).unwrap_or_default();
+        Box::pin(async { ledger })
+    }
+
+    fn ledger_since(&self, timestamp: i64) -> BoxFuture<'_, Vec<LedgerEntry>> {
+        let ledger = (|| {
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection.prepare(&format!(
+                "SELECT {LEDGER_COLUMNS} FROM ledger WHERE timestamp >= ?1 ORDER BY timestamp"
+            ))?;
+            let ledger = statement
+                .query_map([timestamp], Self::read_entry)?
+                .collect::<Result<Vec<_>, _>>();
+            ledger
+        })();
+        let ledger =
+            log_error!("Failed to get ledger from database: {}!"; ledger).unwrap_or_default();
+        Box::pin(async { ledger })
+    }
+
+    fn users(&self) -> BoxFuture<'_, Vec<(String, UserData)>> {
+        let users = (|| {
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection.prepare("SELECT uid, points, roles FROM users")?;
+            let users = statement
+                .query_map((), |row| {
+                    Ok((
+                        row.get(0)?,
+                        UserData {
+                            points: row.get::<_, i64>(1)? as _,
+                            roles: serde_json::from_str(&row.get::<_, String>(2)?)
+                                .unwrap_or_default(),
+                        },
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>();
+            users
+        })();
+        let users = log_error!("Failed to get users from database: {}!"; users).unwrap_or_default();
+        Box::pin(async { users })
+    }
+
+    fn user(&self, uid: &str) -> BoxFuture<'_, Option<UserData>> {
+        let user = {
+            use rusqlite::OptionalExtension;
+            let connection = self.connection.lock().unwrap();
+            log_error!(
+                "Failed to get user from database: {}!";
+                connection
+                    .query_row(
+                        "SELECT points, roles FROM users WHERE uid = ?1",
+                        [uid],
+                        |row| {
+                            Ok(UserData {
+                                points: row.get::<_, i64>(0)? as _,
+                                roles: serde_json::from_str(&row.get::<_, String>(1)?)
+                                    .unwrap_or_default(),
+                            })
+                        },
+                    )
+                    .optional()
+            )
+            .flatten()
+        };
+        Box::pin(async { user })
+    }
+
+    fn set_roles(&self, uid: &str, roles: BTreeSet<Role>) -> BoxFuture<'_, Option<()>> {
+        let result = serde_json::to_string(&roles)
+            .map_err(|err| err.to_string())
+            .and_then(|roles| {
+                self.connection
+                    .lock()
+                    .unwrap()
+                    .execute(
+                        "INSERT INTO users (uid, points, roles) VALUES (?1, 0, ?2)
+                            ON CONFLICT (uid) DO UPDATE SET roles = ?2",
+                        (uid, roles),
+                    )
+                    .map_err(|err| err.to_string())
+            });
+        let result = log_error!("Failed to update user roles: {}!"; result).map(|_| ());
+        Box::pin(async move { result })
+    }
+
+    fn awards(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<Award>> {
+        let awards = (|| {
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection.prepare(
+                "SELECT uid, achievement, timestamp FROM achievements
+                    WHERE ?1 IS NULL OR uid = ?1 ORDER BY timestamp",
+            )?;
+            let awards = statement
+                .query_map([uid], |row| {
+                    Ok(Award {
+                        uid: row.get(0)?,
+                        achievement: row.get(1)?,
+                        timestamp: row.get(2)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>();
+            awards
+        })();
+        let awards =
+            log_error!("Failed to get achievements from database: {}!"; awards).unwrap_or_default();
+        Box::pin(async { awards })
+    }
+
+    fn award(&self, award: Award) -> BoxFuture<'_, Option<Award>> {
+        let inserted = self.connection.lock().unwrap().execute(
+            "INSERT OR IGNORE INTO achievements (uid, achievement, timestamp) VALUES (?1, ?2, ?3)",
+            (&award.uid, &award.achievement, award.timestamp),
+        );
+        let award = log_error!("Failed to award achievement: {}!"; inserted)
+            .filter(|&inserted| inserted > 0)
+            .map(|_| award);
+        Box::pin(async { award })
+    }
+
+    fn add_progress(&self, uid: &str, key: &str, amount: u64) -> BoxFuture<'_, Option<u64>> {
+        let value = self.connection.lock().unwrap().query_row(
+            "INSERT INTO progress (uid, key, value) VALUES (?1, ?2, ?3)
+                ON CONFLICT (uid, key) DO UPDATE SET value = value + ?3
+                RETURNING value",
+            (uid, key, amount as i64),
+            |row| row.get::<_, i64>(0),
+        );
+        let value = log_error!("Failed to update achievement progress: {}!"; value)
+            .map(|value| value as u64);
+        Box::pin(async move { value })
+    }
+
+    fn archives(&self, id: Option<&str>) -> BoxFuture<'_, Vec<SeasonArchive>> {
+        let archives = (|| {
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection.prepare(
+                "SELECT id, closed, standings FROM seasons
+                    WHERE ?1 IS NULL OR id = ?1 ORDER BY closed",
+            )?;
+            let archives = statement
+                .query_map([id], |row| {
+                    Ok(SeasonArchive {
+                        id: row.get(0)?,
+                        closed: row.get(1)?,
+                        standings: serde_json::from_str(&row.get::<_, String>(2)?)
+                            .unwrap_or_default(),
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>();
+            archives
+        })();
+        let archives =
+            log_error!("Failed to get seasons from database: {}!"; archives).unwrap_or_default();
+        Box::pin(async { archives })
+    }
+
+    fn archive(&self, archive: SeasonArchive) -> BoxFuture<'_, Option<SeasonArchive>> {
+        let result = serde_json::to_string(&archive.standings)
+            .map_err(|err| err.to_string())
+            .and_then(|standings| {
+                self.connection
+                    .lock()
+                    .unwrap()
+                    .execute(
+                        "INSERT OR IGNORE INTO seasons (id, closed, standings) VALUES (?1, ?2, ?3)",
+                        (&archive.id, archive.closed, standings),
+                    )
+                    .map_err(|err| err.to_string())
+            });
+        let archive = log_error!("Failed to archive season: {}!"; result)
+            .filter(|&inserted| inserted > 0)
+            .map(|_| archive);
+        Box::pin(async { archive })
+    }
+}
+
+// * --------------------------------- Memory & JSON -------------------------------- * //
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct MemoryData {
+    users: BTreeMap<String, UserData>,
+    ledger: Vec<LedgerEntry>,
+    #[serde(default)]
+    awards: Vec<Award>,
+    /// Achievement progress by uid and key
+    #[serde(default)]
+    progress: BTreeMap<String, BTreeMap<String, u64>>,
+    #[serde(default)]
+    seasons: Vec<SeasonArchive>,
+}
+
+/// Keeps points in memory, optionally saving them to a json file on every change
+#[derive(Default)]
+pub struct MemoryStore {
+    path: Option<PathBuf>,
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let data = if path.exists() {
+            log_error!(
+                "Failed to load points from {}: {}!", path.display();
+                std::fs::read_to_string(&path)
+            )
+            .and_then(|data| {
+                log_error!(
+                    "Failed to parse points from {}: {}!", path.display();
+                    serde_json::from_str(&data)
+                )
+            })
+            .unwrap_or_default()
+        } else {
+            MemoryData::default()
+        };
+        Self {
+            path: Some(path),
+            data: Mutex::new(data),
+        }
+    }
+
+    fn save(&self, data: &MemoryData) -> Option<()> {
+        if let Some(path) = &self.path {
+            let json =
+                log_error!("Failed to serialize points: {}!"; serde_json::to_string_pretty(data))?;
+            log_error!("Failed to save points to {}: {}!", path.display(); std::fs::write(path, json))?;
+        }
+        Some(())
+    }
+}
+
+impl PointsStore for MemoryStore {
+    fn append(&self, mut entry: LedgerEntry) -> BoxFuture<'_, Option<LedgerEntry>> {
+        let mut data = self.data.lock().unwrap();
+        if data.ledger.iter().any(|other| other.id == entry.id) {
+            log::error!("Ledger entry {:?} already exists!", entry.id);
+            return Box::pin(async { None });
+        }
+        let user = data.users.entry(entry.uid.clone()).or_default();
+        let previous = user.points;
+        user.points = user.points.saturating_add_signed(entry.amount);
+        entry.amount = user.points as i64 - previous as i64;
+        data.ledger.push(entry.clone());
+
+        // * Roll back if the change couldn't be persisted
+        let entry = if self.save(&data).is_some() {
+            Some(entry)
+        } else {
+            data.ledger.pop();
+            data.users.get_mut(&entry.uid).unwrap().points = previous;
+            None
+        };
+        Box::pin(async { entry })
+    }
+
+    fn entry(&self, id: &str) -> BoxFuture<'_, Option<LedgerEntry>> {
+        let entry = self
+            .data
+            .lock()
+            .unwrap()
+            .ledger
+            .iter()
+            .find(|entry| entry.id == id)
+            .cloned();
+        Box::pin(async { entry })
+    }
+
+    fn ledger(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<LedgerEntry>> {
+        let ledger = self
+            .data
+            .lock()
+            .unwrap()
+            .ledger
+            .iter()
+            .filter(|entry| uid.map_or(true, |uid| entry.uid == uid))
+            .cloned()
+            .collect();
+        Box::pin(async { ledger })
+    }
+
+    fn ledger_since(&self, timestamp: i64) -> BoxFuture<'_, Vec<LedgerEntry>> {
+        let ledger = self
+            .data
+            .lock()
+            .unwrap()
+            .ledger
+            .iter()
+            .filter(|entry| entry.timestamp >= timestamp)
+            .cloned()
+            .collect();
+        Box::pin(async { ledger })
+    }
+
+    fn users(&self) -> BoxFuture<'_, Vec<(String, UserData)>> {
+        let users = self
+            .data
+            .lock()
+            .unwrap()
+            .users
+            .iter()
+            .map(|(uid, user)| (uid.clone(), user.clone()))
+            .collect();
+        Box::pin(async { users })
+    }
+
+    fn user(&self, uid: &str) -> BoxFuture<'_, Option<UserData>> {
+        let user = self.data.lock().unwrap().users.get(uid).cloned();
+        Box::pin(async { user })
+    }
+
+    fn set_roles(&self, uid: &str, roles: BTreeSet<Role>) -> BoxFuture<'_, Option<()>> {
+        let mut data = self.data.lock().unwrap();
+        let user = data.users.entry(uid.to_owned()).or_default();
+        let previous = std::mem::replace(&mut user.roles, roles);
+
+        // * Roll back if the change couldn't be persisted
+        let result = self.save(&data);
+        if result.is_none() {
+            data.users.get_mut(uid).unwrap().roles = previous;
+        }
+        Box::pin(async move { result })
+    }
+
+    fn awards(&self, uid: Option<&str>) -> BoxFuture<'_, Vec<Award>> {
+        let awards = self
+            .data
+            .lock()
+            .unwrap()
+            .awards
+            .iter()
+            .filter(|award| uid.map_or(true, |uid| award.uid == uid))
+            .cloned()
+            .collect();
+        Box::pin(async { awards })
+    }
+
+    fn award(&self, award: Award) -> BoxFuture<'_, Option<Award>> {
+        let mut data = self.data.lock().unwrap();
+        let award = if data
+            .awards
+            .iter()
+            .any(|other| other.uid == award.uid && other.achievement == award.achievement)
+        {
+            None
+        } else {
+            data.awards.push(award.clone());
+
+            // * Roll back if the change couldn't be persisted
+            if self.save(&data).is_some() {
+                Some(award)
+            } else {
+                data.awards.pop();
+                None
+            }
+        };
+        Box::pin(async { award })
+    }
+
+    fn add_progress(&self, uid: &str, key: &str, amount: u64) -> BoxFuture<'_, Option<u64>> {
+        let mut data = self.data.lock().unwrap();
+        let value = data
+            .progress
+            .entry(uid.to_owned())
+            .or_default()
+            .entry(key.to_owned())
+            .or_default();
+        let previous = *value;
+        *value = value.saturating_add(amount);
+        let value = *value;
+
+        // * Roll back if the change couldn't be persisted
+        let result = self.save(&data).map(|_| value);
+        if result.is_none() {
+            data.progress
+                .get_mut(uid)
+                .unwrap()
+                .insert(key.to_owned(), previous);
+        }
+        Box::pin(async move { result })
+    }
+
+    fn archives(&self, id: Option<&str>) -> BoxFuture<'_, Vec<SeasonArchive>> {
+        let archives = self
+            .data
+            .lock()
+            .unwrap()
+            .seasons
+            .iter()
+            .filter(|archive| id.map_or(true, |id| archive.id == id))
+            .cloned()
+            .collect();
+        Box::pin(async { archives })
+    }
+
+    fn archive(&self, archive: SeasonArchive) -> BoxFuture<'_, Option<SeasonArchive>> {
+        let mut data = self.data.lock().unwrap();
+        let archive = if data.seasons.iter().any(|other| other.id == archive.id) {
+            None
+        } else {
+            data.seasons.push(archive.clone());
+
+            // * Roll back if the change couldn't be persisted
+            if self.save(&data).is_some() {
+                Some(archive)
+            } else {
+                data.seasons.pop();
+                None
+            }
+        };
+        Box::pin(async { archive })
+    }
+}
+
+// * ------------------------------------- Shared ----------------------------------- * //
+static STORE: tokio::sync::OnceCell<Box<dyn PointsStore>> = tokio::sync::OnceCell::const_new();
+
+/// How long to wait before trying to open the points store again
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+async fn open_store() -> Option<Box<dyn PointsStore>> {
+    let path = config().points.path.clone();
+    let store: Box<dyn PointsStore> = match config().points.backend {
+        PointsBackend::Firestore => Box::new(FirestoreStore::connect().await?),
+        PointsBackend::Sqlite => Box::new(SqliteStore::open(
+            path.unwrap_or("state/points.db".to_owned()),
+        )?),
+        PointsBackend::Json => Box::new(MemoryStore::load(
+            path.unwrap_or("state/points.json".to_owned()),
+        )),
+        PointsBackend::Memory => Box::new(MemoryStore::new()),
+    };
+    Some(store)
+}
+
+/// The points store selected in the config, opened once and shared.
+/// Keeps retrying if it can't be opened, points are never silently kept in memory only
+pub async fn store() -> &'static dyn PointsStore {
+    STORE
+        .get_or_init(|| async {
+            loop {
+                if let Some(store) = open_store().await {
+                    break store;
+                }
+                log::error!(
+                    "Failed to open the points store, retrying in {}s!",
+                    RETRY_INTERVAL.as_secs()
+                );
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        })
+        .await
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stores() -> Vec<Box<dyn PointsStore>> {
+        vec![
+            Box::new(MemoryStore::new()),
+            Box::new(SqliteStore::open(":memory:").unwrap()),
+        ]
+    }
+
+    fn entry(id: &str, uid: &str, amount: i64, timestamp: i64) -> LedgerEntry {
+        LedgerEntry {
+            id: id.to_owned(),
+            uid: uid.to_owned(),
+            amount,
+            game: "test".to_owned(),
+            match_id: "match".to_owned(),
+            timestamp,
+            reverses: None,
+        }
+    }
+
+    async fn points(store: &dyn PointsStore, uid: &str) -> u64 {
+        store
+            .users()
+            .await
+            .into_iter()
+            .find(|(user, _)| user == uid)
+            .map_or(0, |(_, user)| user.points)
+    }
+
+    #[tokio::test]
+    async fn append_clamps_at_zero() {
+        for store in stores() {
+            store.append(entry("a", "player", 10, 0)).await.unwrap();
+            let entry = store.append(entry("b", "player", -25, 1)).await.unwrap();
+            assert_eq!(entry.amount, -10);
+            assert_eq!(store.entry("b").await.unwrap().amount, -10);
+            assert_eq!(points(store.as_ref(), "player").await, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_duplicate_ids() {
+        for store in stores() {
+            store.append(entry("a", "player", 10, 0)).await.unwrap();
+            assert_eq!(store.append(entry("a", "player", 10, 1)).await, None);
+            assert_eq!(store.ledger(None).await.len(), 1);
+            assert_eq!(points(store.as_ref(), "player").await, 10);
+        }
+    }
+
+    #[tokio::test]
+    async fn reverses_only_once() {
+        for store in stores() {
+            store.append(entry("a", "player", 10, 0)).await.unwrap();
+            let reversal = store.reverse("a").await.unwrap();
+            assert_eq!(reversal.amount, -10);
+            assert_eq!(reversal.reverses.as_deref(), Some("a"));
+            assert_eq!(store.reverse("a").await, None);
+            assert_eq!(store.reverse(&reversal.id).await, None);
+            assert_eq!(store.reverse("missing").await, None);
+            assert_eq!(points(store.as_ref(), "player").await, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_ledger_by_user() {
+        for store in stores() {
+            store.append(entry("a", "first", 10, 0)).await.unwrap();
+            store.append(entry("b", "second", 20, 1)).await.unwrap();
+            store.append(entry("c", "first", 30, 2)).await.unwrap();
+            let ids = |ledger: Vec<LedgerEntry>| {
+                ledger.into_iter().map(|entry| entry.id).collect::<Vec<_>>()
+            };
+            assert_eq!(ids(store.ledger(Some("first")).await), ["a", "c"]);
+            assert!(store.ledger(Some("nobody")).await.is_empty());
+            assert_eq!(ids(store.ledger(None).await), ["a", "b", "c"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn balances_match_ledger() {
+        for store in stores() {
+            let amounts = [("first", 10), ("second", 5), ("first", -30), ("second", 7)];
+            for (index, (uid, amount)) in amounts.into_iter().enumerate() {
+                let id = index.to_string();
+                store
+                    .append(entry(&id, uid, amount, index as _))
+                    .await
+                    .unwrap();
+            }
+            store.reverse("1").await.unwrap();
+
+            for (uid, user) in store.users().await {
+                let total: i64 = store
+                    .ledger(Some(&uid))
+                    .await
+                    .iter()
+                    .map(|entry| entry.amount)
+                    .sum();
+                assert_eq!(total, user.points as i64, "{uid}");
+            }
+            assert_eq!(points(store.as_ref(), "first").await, 0);
+            assert_eq!(points(store.as_ref(), "second").await, 7);
+        }
+    }
+}
@@ -1,6 +1,18 @@
+pub mod moderation;
+pub mod rating;
+
 use futures_util::stream::{SplitSink, SplitStream};
 use scheduler::*;
-use std::{future::Future, pin::Pin, sync::Mutex};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 use warp::filters::ws::{Message, WebSocket};
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -8,199 +20,1285 @@ pub enum State {
     #[default]
     None,
     WaitingForPlayers(std::time::Instant),
+    /// Matched players confirm they are ready
+    ReadyCheck(std::time::Instant),
     Playing,
     Finished(std::time::Instant),
 }
 
+/// How a connection was handed to the game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Session {
+    /// The player joins a new match
+    Joined,
+    /// The player reconnected to their running match, and needs its full state again
+    Resumed,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReadyCheck {
+    /// How long players have to confirm
+    pub timeout: std::time::Duration,
+    /// Players that didn't confirm go back to the end of the queue instead of being dropped
+    pub requeue: bool,
+}
+
+/// Ready checks a requeued player can miss in a row before they are dropped
+const MAX_MISSED_READY_CHECKS: u32 = 2;
+
 #[derive(Clone, Debug)]
-pub struct Queue {
-    queue: Vec<(String, String)>,
+struct Waiting {
+    uid: String,
+    name: String,
+    joined: std::time::Instant,
+    /// Private room code, room members only play each other
+    room: Option<String>,
+}
+
+/// Assumed match length until a match has finished
+const DEFAULT_MATCH_DURATION: std::time::Duration = std::time::Duration::from_secs(180);
+/// Finished matches the average match duration is taken over
+const MATCH_DURATION_SAMPLES: usize = 10;
+
+/// Public view of a queue, without uids or room codes
+#[derive(Clone, Debug, Serialize)]
+pub struct Status {
+    /// Id the queue's routes are under
+    pub id: &'static str,
+    /// Game players are rated in
+    pub game: String,
+    pub state: &'static str,
+    pub queue_length: usize,
+    /// Names of the players in the running or next match
+    pub players: Vec<String>,
+    /// In seconds
+    pub average_match: f64,
+    pub queue: Vec<QueueEntry>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueueEntry {
+    pub position: usize,
+    pub name: String,
+    /// Waiting in a private room
+    pub private: bool,
+    /// Estimated seconds until this player's match starts
+    pub eta: f64,
+}
+
+/// Characters of room codes, without ones that are easy to mix up
+const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+fn new_room_code() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::UNIX_EPOCH
+            .elapsed()
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    let mut value = hasher.finish();
+    (0..5)
+        .map(|_| {
+            let index = (value % ROOM_CODE_ALPHABET.len() as u64) as usize;
+            value /= ROOM_CODE_ALPHABET.len() as u64;
+            ROOM_CODE_ALPHABET[index] as char
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Inner {
+    queue: Vec<Waiting>,
     current_game: Vec<(String, String)>,
+    /// Rating the next game is matched around and since when its first player waits
+    anchor: Option<(f64, std::time::Instant)>,
+    /// Private room the next game is for
+    room: Option<String>,
+    /// Rooms moderators moved to the front of the queue
+    priority: BTreeSet<String>,
+    /// Players whose connection dropped before their game started, and since when
+    absent: BTreeMap<String, std::time::Instant>,
+    /// Id of each player's latest connection
+    connections: BTreeMap<String, u64>,
+    /// Players that confirmed the ready check
+    ready: BTreeSet<String>,
+    /// Players dropped for not confirming, until they are told so
+    not_ready: BTreeSet<String>,
+    /// Ready checks every player missed in a row
+    missed: BTreeMap<String, u32>,
+    /// Requeued players kept out of the match they weren't ready for
+    barred: BTreeSet<String>,
+    /// Since when the next match has its first player, for filling in AI
+    matched: std::time::Instant,
+    /// When the running match started
+    match_started: Option<std::time::Instant>,
     state: State,
 }
 
-impl Queue {
+impl Inner {
     fn new() -> Self {
         Self {
             queue: Vec::new(),
             current_game: Vec::new(),
+            anchor: None,
+            room: None,
+            priority: BTreeSet::new(),
+            absent: BTreeMap::new(),
+            connections: BTreeMap::new(),
+            ready: BTreeSet::new(),
+            not_ready: BTreeSet::new(),
+            missed: BTreeMap::new(),
+            barred: BTreeSet::new(),
+            matched: std::time::Instant::now(),
+            match_started: None,
             state: State::WaitingForPlayers(std::time::Instant::now()),
         }
     }
 
-    pub const fn empty() -> Self {
-        Self {
-            queue: Vec::new(),
-            current_game: Vec::new(),
-            state: State::None,
+    /// Clear the finished match. Waiting players and their connections stay
+    fn restart(&mut self) {
+        self.current_game.clear();
+        self.anchor = None;
+        self.room = None;
+        self.match_started = None;
+        self.ready.clear();
+        self.barred.clear();
+        self.state = State::WaitingForPlayers(std::time::Instant::now());
+    }
+
+    /// Register a new connection of the player, replacing older ones
+    fn connect(&mut self, uid: &str) -> u64 {
+        static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+        let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+        self.connections.insert(uid.to_owned(), connection);
+        self.absent.remove(uid);
+        connection
+    }
+
+    /// Mark the player absent, unless they have reconnected since
+    fn disconnect(&mut self, uid: &str, connection: u64) {
+        if self.connections.get(uid) == Some(&connection) {
+            self.connections.remove(uid);
+            self.absent
+                .insert(uid.to_owned(), std::time::Instant::now());
         }
     }
-}
 
-static QUEUE: Mutex<Queue> = Mutex::new(Queue::empty());
+    /// Whether the player is in the current match, unless it's already over
+    fn is_matched(&self, uid: &str) -> bool {
+        !matches!(self.state, State::Finished(_))
+            && self.current_game.iter().any(|(user, _)| user == uid)
+    }
 
-pub fn get_state() -> State {
-    QUEUE.lock().unwrap().state.clone()
-}
+    /// Count the player as ready, only while their match is waiting for them to confirm
+    fn confirm_ready(&mut self, uid: &str) {
+        if matches!(self.state, State::ReadyCheck(_)) && self.is_matched(uid) {
+            self.ready.insert(uid.to_owned());
+        }
+    }
 
-pub fn set_state(state: State) {
-    QUEUE.lock().unwrap().state = state;
-}
+    /// Take a player out of the queue and the next match. Running matches are left to the game
+    fn remove(&mut self, uid: &str) {
+        self.queue.retain(|waiting| waiting.uid != uid);
+        if !matches!(self.state, State::Playing | State::Finished(_)) {
+            self.current_game.retain(|(user, _)| user != uid);
+            self.ready.remove(uid);
+        }
+        self.absent.remove(uid);
+    }
 
-pub fn get_players() -> Vec<(String, String)> {
-    QUEUE.lock().unwrap().current_game.clone()
-}
+    /// Remove players that didn't reconnect in time, freeing their slots
+    fn drop_absent(&mut self) {
+        let grace = std::time::Duration::from_secs(config().queue.reconnect_grace);
+        let expired = self
+            .absent
+            .iter()
+            .filter(|(_, since)| since.elapsed() > grace)
+            .map(|(uid, _)| uid.clone())
+            .collect::<Vec<_>>();
+        for uid in expired {
+            self.absent.remove(&uid);
+            self.queue.retain(|waiting| waiting.uid != uid);
+            if matches!(self.state, State::WaitingForPlayers(_)) {
+                self.current_game.retain(|(user, _)| user != &uid);
+            }
+            log::info!("{uid} left the queue");
+        }
+    }
+
+    /// Start the match once everyone is ready. After the timeout, unconfirmed players
+    /// are requeued or dropped and the match goes back to waiting for players
+    fn check_ready(&mut self, since: std::time::Instant, ready_check: ReadyCheck) {
+        if self
+            .current_game
+            .iter()
+            .all(|(uid, _)| self.ready.contains(uid))
+        {
+            for (uid, _) in &self.current_game {
+                self.missed.remove(uid);
+            }
+            self.ready.clear();
+            self.barred.clear();
+            self.state = State::Playing;
+            return;
+        }
+        if since.elapsed() < ready_check.timeout {
+            return;
+        }
+
+        let (ready, idle): (Vec<_>, Vec<_>) = std::mem::take(&mut self.current_game)
+            .into_iter()
+            .partition(|(uid, _)| self.ready.contains(uid));
+        self.current_game = ready;
+        for (uid, name) in idle {
+            let missed = self.missed.entry(uid.clone()).or_default();
+            *missed += 1;
+            if ready_check.requeue
+                && *missed < MAX_MISSED_READY_CHECKS
+                && !self.absent.contains_key(&uid)
+            {
+                log::info!("{name} wasn't ready, back to the queue");
+                // * Otherwise they would be matched right back into the same match
+                if !self.current_game.is_empty() {
+                    self.barred.insert(uid.clone());
+                }
+                self.queue.push(Waiting {
+                    uid,
+                    name,
+                    joined: std::time::Instant::now(),
+                    room: self.room.clone(),
+                });
+            } else {
+                log::info!("{name} wasn't ready, dropped");
+                self.missed.remove(&uid);
+                self.absent.remove(&uid);
+                self.not_ready.insert(uid);
+            }
+        }
+        // * The wait for AI goes on from where it was, unless the match is empty again
+        self.state = State::WaitingForPlayers(if self.current_game.is_empty() {
+            std::time::Instant::now()
+        } else {
+            self.matched
+        });
+    }
+
+    /// Create a private room for a waiting player, or move them into an existing one with
+    /// fewer than `capacity` players. Returns the locale key of the error
+    fn join_room(
+        &mut self,
+        uid: &str,
+        code: Option<&str>,
+        capacity: usize,
+    ) -> Result<(), &'static str> {
+        let code = match code {
+            Some(code) => {
+                let code = code.trim().to_uppercase();
+                let members = self
+                    .queue
+                    .iter()
+                    .filter(|waiting| waiting.uid != uid && waiting.room.as_ref() == Some(&code))
+                    .count();
+                if members == 0 {
+                    return Err("queue-no-room");
+                }
+                // * Everyone in a room plays in the same match
+                if members >= capacity {
+                    return Err("queue-room-full");
+                }
+                code
+            }
+            None => loop {
+                let code = new_room_code();
+                if !self
+                    .queue
+                    .iter()
+                    .any(|waiting| waiting.room.as_ref() == Some(&code))
+                {
+                    break code;
+                }
+            },
+        };
+        let Some(waiting) = self.queue.iter_mut().find(|waiting| waiting.uid == uid) else {
+            return Err("queue-no-room");
+        };
+        log::info!("{} is in room {code}", waiting.name);
+        waiting.room = Some(code);
+        Ok(())
+    }
 
-pub fn restart() {
-    *QUEUE.lock().unwrap() = Queue::new();
+    /// Move waiting players with a rating close enough to the game's into it, longest waiting
+    /// first. The accepted rating gap widens while the first player waits.
+    /// A private room is matched as a unit and only with its own members
+    fn matchmake(&mut self, game: &str, required_players: usize) {
+        if self.current_game.is_empty() {
+            // * The match players were barred from is gone
+            self.barred.clear();
+            // * Rooms moderators picked go first, then whoever waits longest
+            let present = |waiting: &Waiting| !self.absent.contains_key(&waiting.uid);
+            let prioritized = |waiting: &Waiting| {
+                waiting
+                    .room
+                    .as_ref()
+                    .is_some_and(|room| self.priority.contains(room))
+            };
+            let Some(index) = self
+                .queue
+                .iter()
+                .position(|waiting| present(waiting) && prioritized(waiting))
+                .or_else(|| self.queue.iter().position(present))
+            else {
+                return;
+            };
+            let anchor = self.queue.remove(index);
+            if let Some(room) = &anchor.room {
+                self.priority.remove(room);
+            }
+            self.anchor = Some((rating::rating(game, &anchor.uid).rating, anchor.joined));
+            self.room = anchor.room;
+            self.current_game.push((anchor.uid, anchor.name));
+        }
+        let Some((anchor_rating, since)) = self.anchor else {
+            return;
+        };
+
+        let gap =
+            config().queue.rating_gap + config().queue.gap_growth * since.elapsed().as_secs_f64();
+        let mut index = 0;
+        while index < self.queue.len() && self.current_game.len() < required_players {
+            let waiting = &self.queue[index];
+            let fits = match &self.room {
+                Some(room) => waiting.room.as_ref() == Some(room),
+                None => {
+                    waiting.room.is_none()
+                        && (rating::rating(game, &waiting.uid).rating - anchor_rating).abs() <= gap
+                }
+            };
+            if fits
+                && !self.absent.contains_key(&waiting.uid)
+                && !self.barred.contains(&waiting.uid)
+            {
+                let player = self.queue.remove(index);
+                self.current_game.push((player.uid, player.name));
+            } else {
+                index += 1;
+            }
+        }
+    }
 }
 
-pub fn make_queue<F>(
+/// Handle to a queue with its own players, settings and state machine.
+/// Clones share the same queue, so a plugin can keep one and hand another to its server
+#[derive(Clone)]
+pub struct Queue {
+    id: &'static str,
+    game: Arc<str>,
     required_players: usize,
     max_queue_size: usize,
     wait_time: Option<std::time::Duration>,
-    player: &'static F,
-) -> impl Fn(String, WebSocket) -> Pin<Box<dyn Future<Output = ()> + Send>>
-where
-    F: Fn(String, String, SplitSink<WebSocket, Message>, SplitStream<WebSocket>) + Sync + Send,
-{
-    {
-        let mut queue = QUEUE.lock().unwrap();
-        *queue = Queue::new();
+    ready_check: Option<ReadyCheck>,
+    inner: Arc<Mutex<Inner>>,
+    /// Lengths of the last finished matches, kept across restarts
+    durations: Arc<Mutex<VecDeque<std::time::Duration>>>,
+    /// Bumped on every change, wakes the matchmaking loop and waiting players
+    changes: Arc<tokio::sync::watch::Sender<u64>>,
+}
+
+impl Queue {
+    /// `id` tells queues apart in routes, so several queues can rate players in the same `game`.
+    /// Missing players are filled in by AI after `wait_time`, if it is set
+    pub fn new(
+        id: &'static str,
+        game: &str,
+        required_players: usize,
+        max_queue_size: usize,
+        wait_time: Option<std::time::Duration>,
+    ) -> Self {
+        let queue = Self {
+            id,
+            game: game.into(),
+            required_players,
+            max_queue_size,
+            wait_time,
+            ready_check: None,
+            inner: Arc::new(Mutex::new(Inner::new())),
+            durations: Arc::new(Mutex::new(VecDeque::new())),
+            changes: Arc::new(tokio::sync::watch::channel(0).0),
+        };
+
+        // * Banned players leave right away, without keeping the queue alive
+        let (inner, changes) = (Arc::downgrade(&queue.inner), Arc::downgrade(&queue.changes));
+        moderation::on_kick(id, move |uid| {
+            if let (Some(inner), Some(changes)) = (inner.upgrade(), changes.upgrade()) {
+                inner.lock().unwrap().remove(uid);
+                changes.send_modify(|version| *version = version.wrapping_add(1));
+            }
+        });
+        queue
     }
-    spawn_in_server_runtime(async move {
-        loop {
-            {
-                let mut queue = QUEUE.lock().unwrap();
-                match queue.state.clone() {
-                    State::None => {
-                        log::error!("Invalid queue state: None!");
-                        *queue = Queue::new();
-                    }
-                    State::WaitingForPlayers(time) => {
-                        if !queue.queue.is_empty() {
-                            let players_to_complete = required_players - queue.current_game.len();
-                            let players_to_provide = players_to_complete.min(queue.queue.len());
-                            let new_players =
-                                queue.queue.drain(..players_to_provide).collect::<Vec<_>>();
-                            queue.current_game.extend(new_players);
-                            if queue.current_game.len() >= required_players {
-                                queue.state = State::Playing;
-                            }
-                        }
-                        if queue.current_game.is_empty() {
-                            queue.state = State::WaitingForPlayers(std::time::Instant::now())
-                        } else if Some(time.elapsed()) >= wait_time {
-                            queue.state = State::Playing;
-                        }
+
+    pub fn id(&self) -> &'static str {
+        self.id
+    }
+
+    /// Have matched players confirm they are ready before the match starts
+    pub fn with_ready_check(mut self, ready_check: ReadyCheck) -> Self {
+        self.ready_check = Some(ready_check);
+        self
+    }
+
+    pub fn state(&self) -> State {
+        self.inner.lock().unwrap().state.clone()
+    }
+
+    pub fn set_state(&self, state: State) {
+        self.update(|inner| {
+            if let State::Finished(_) = state {
+                if let Some(started) = inner.match_started.take() {
+                    let mut durations = self.durations.lock().unwrap();
+                    durations.push_back(started.elapsed());
+                    if durations.len() > MATCH_DURATION_SAMPLES {
+                        durations.pop_front();
                     }
-                    State::Playing => (),
-                    State::Finished(_) => (),
                 }
             }
-            tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+            inner.state = state;
+        });
+    }
+
+    /// Change the queue and let everyone watching it know
+    fn update<T>(&self, f: impl FnOnce(&mut Inner) -> T) -> T {
+        let result = f(&mut self.inner.lock().unwrap());
+        self.notify();
+        result
+    }
+
+    fn notify(&self) {
+        self.changes
+            .send_modify(|version| *version = version.wrapping_add(1));
+    }
+
+    pub fn players(&self) -> Vec<(String, String)> {
+        self.inner.lock().unwrap().current_game.clone()
+    }
+
+    /// Number of players waiting for the next game
+    pub fn queue_length(&self) -> usize {
+        self.inner.lock().unwrap().queue.len()
+    }
+
+    /// Average length of the last finished matches
+    pub fn average_match(&self) -> std::time::Duration {
+        let durations = self.durations.lock().unwrap();
+        if durations.is_empty() {
+            return DEFAULT_MATCH_DURATION;
         }
-    });
+        durations.iter().sum::<std::time::Duration>() / durations.len() as u32
+    }
 
-    move |uid: String, websocket: warp::filters::ws::WebSocket| {
-        Box::pin(async move {
-            use futures_util::stream::StreamExt;
-            use futures_util::SinkExt;
-            let (mut tx, rx) = websocket.split();
+    /// Snapshot of the queue with an estimated wait for every position. Matches are assumed
+    /// to take the average time and to be filled by the players in order
+    pub fn status(&self) -> Status {
+        let average = self.average_match();
+        let inner = self.inner.lock().unwrap();
+        let (state, time_left) = match &inner.state {
+            State::None => ("none", std::time::Duration::ZERO),
+            State::WaitingForPlayers(_) => ("waiting", std::time::Duration::ZERO),
+            State::ReadyCheck(_) => ("ready-check", average),
+            State::Playing => (
+                "playing",
+                average.saturating_sub(
+                    inner
+                        .match_started
+                        .map(|started| started.elapsed())
+                        .unwrap_or_default(),
+                ),
+            ),
+            State::Finished(_) => ("finished", std::time::Duration::ZERO),
+        };
+        Status {
+            id: self.id,
+            game: self.game.to_string(),
+            state,
+            queue_length: inner.queue.len(),
+            players: inner
+                .current_game
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect(),
+            average_match: average.as_secs_f64(),
+            queue: inner
+                .queue
+                .iter()
+                .enumerate()
+                .map(|(index, waiting)| {
+                    // * Rooms wait in line as a unit, at the place of their first member
+                    let index = inner
+                        .queue
+                        .iter()
+                        .position(|other| waiting.room.is_some() && other.room == waiting.room)
+                        .unwrap_or(index);
+                    QueueEntry {
+                        position: index + 1,
+                        name: waiting.name.clone(),
+                        private: waiting.room.is_some(),
+                        eta: (time_left + average * (index / self.required_players.max(1)) as u32)
+                            .as_secs_f64(),
+                    }
+                })
+                .collect(),
+        }
+    }
 
-            macro_rules! try_send {
-                ($message: expr) => {
-                    try_log!("Send error: {}"; tx.send($message).await)
-                };
+    /// Routes under `/leaderboard/api/queue/<id>`, the status as JSON
+    /// and as server-sent events on every change under `events`
+    pub fn routes(
+        &self,
+    ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection>
+           + Clone
+           + Send
+           + Sync
+           + 'static {
+        use warp::Filter;
+
+        let queue = self.clone();
+        let status = warp::path::end().map(move || warp::reply::json(&queue.status()));
+        let queue = self.clone();
+        let events = warp::path("events").and(warp::path::end()).map(move || {
+            // * The current status first, then a new one on every change
+            let events = futures_util::stream::unfold(
+                (queue.clone(), queue.changes.subscribe(), true),
+                |(queue, mut changes, first)| async move {
+                    if !first {
+                        changes.changed().await.ok()?;
+                    }
+                    let event = warp::sse::Event::default()
+                        .json_data(&queue.status())
+                        .unwrap_or_default();
+                    Some((
+                        Ok::<_, std::convert::Infallible>(event),
+                        (queue, changes, false),
+                    ))
+                },
+            );
+            warp::sse::reply(warp::sse::keep_alive().stream(events))
+        });
+        warp::path("queue")
+            .and(warp::path(self.id))
+            .and(status.or(events).unify())
+    }
+
+    /// Get ready for the next match, keeping everyone that waits for it
+    pub fn restart(&self) {
+        self.update(Inner::restart);
+    }
+
+    /// Moderator commands, including [`moderation::command`]s,
+    /// returns false if the command is not a queue command
+    pub fn command(&self, command: &str) -> bool {
+        let (cmd, args) = command.split_once(' ').unwrap_or((command, ""));
+        let mut inner = self.inner.lock().unwrap();
+        match cmd {
+            "rooms" => {
+                let mut rooms = BTreeMap::<&str, Vec<&str>>::new();
+                for waiting in &inner.queue {
+                    if let Some(room) = &waiting.room {
+                        rooms
+                            .entry(room.as_str())
+                            .or_default()
+                            .push(waiting.name.as_str());
+                    }
+                }
+                for (room, players) in rooms {
+                    let priority = if inner.priority.contains(room) {
+                        " (prioritized)"
+                    } else {
+                        ""
+                    };
+                    log::info!("Room {room}{priority}: {}", players.join(", "));
+                }
+            }
+            "prioritize" => {
+                let code = args.trim().to_uppercase();
+                if inner
+                    .queue
+                    .iter()
+                    .any(|waiting| waiting.room.as_ref() == Some(&code))
+                {
+                    log::info!("Room {code} plays next");
+                    inner.priority.insert(code);
+                    self.notify();
+                } else {
+                    log::error!("No room {code:?} in the queue!");
+                }
             }
+            _ => return moderation::command(command),
+        }
+        true
+    }
 
-            // * Get meta
-            let name = {
-                get_firebase_user(uid.clone())
-                    .await
-                    .and_then(|user| user.display_name.map(|name| format!("@{name}")))
-                    .unwrap_or("Someone".to_owned())
+    /// Show the running ready check on stream
+    pub fn draw_ready_check(&self, context: &cairo::Context, width: f64, height: f64) {
+        let (ready, total, time_left) = {
+            let inner = self.inner.lock().unwrap();
+            let (State::ReadyCheck(since), Some(ready_check)) = (&inner.state, self.ready_check)
+            else {
+                return;
             };
+            (
+                inner
+                    .current_game
+                    .iter()
+                    .filter(|(uid, _)| inner.ready.contains(uid))
+                    .count(),
+                inner.current_game.len(),
+                ready_check.timeout.saturating_sub(since.elapsed()),
+            )
+        };
+        let text = tr(
+            &i18n::stream_language(),
+            "ready-check",
+            &[
+                ("ready", &ready),
+                ("total", &total),
+                ("seconds", &time_left.as_secs()),
+            ],
+        );
 
-            // * Join the queue
-            let mut position_in_queue = 0;
-            {
-                let success = {
-                    let mut queue = QUEUE.lock().unwrap();
-                    if queue.queue.len() > max_queue_size {
-                        false
-                    } else if queue.queue.iter().any(|(user, _)| user == &uid)
-                        || queue.current_game.iter().any(|(user, _)| user == &uid)
-                    {
-                        true
+        theme().set_color(context, "banner_background");
+        context.rectangle(0.0, height * 0.4, width, height * 0.2);
+        log_error!("{}"; context.fill());
+
+        theme().set_color(context, "text");
+        fit_font_size(
+            context,
+            &text,
+            height / 10.0,
+            height / 30.0,
+            width * 0.9,
+            height * 0.2,
+        );
+        let lines = wrap_text(context, &text, width * 0.9);
+        show_lines(context, &lines, width / 2.0, height / 2.0, true);
+    }
+
+    /// Show the next players in the queue in the top right corner of the stream
+    pub fn draw_up_next(&self, context: &cairo::Context, width: f64, height: f64) {
+        const SHOWN: usize = 3;
+        let status = self.status();
+        if status.queue.is_empty() {
+            return;
+        }
+        let line = height / 30.0;
+        let lines = status.queue.len().min(SHOWN) + 1;
+        let (x, y) = (width * 0.78, line * 0.5);
+
+        theme().set_color(context, "banner_background");
+        context.rectangle(x, y, width * 0.21, line * (lines as f64 + 0.5));
+        log_error!("{}"; context.fill());
+
+        theme().set_color(context, "text");
+        let title = tr(&i18n::stream_language(), "up-next", &[]);
+        let entries = status.queue.iter().take(SHOWN).map(|entry| {
+            let eta = entry.eta.round() as u64;
+            format!(
+                "{}. {} ~{}:{:02}",
+                entry.position,
+                entry.name,
+                eta / 60,
+                eta % 60
+            )
+        });
+        for (index, text) in std::iter::once(title).chain(entries).enumerate() {
+            // * Names too long even at the smallest size are cut short
+            fit_font_size(context, &text, line * 0.8, line * 0.4, width * 0.2, line);
+            let text = ellipsize(context, &text, width * 0.2);
+            context.move_to(x + width * 0.005, y + line * (index as f64 + 1.0));
+            log_error!("{}"; context.show_text(&text));
+        }
+    }
+
+    /// Advance the state machine, returns when it has to run again without other changes
+    fn step(&self) -> Option<std::time::Instant> {
+        let mut inner = self.inner.lock().unwrap();
+        let before = (
+            inner.state.clone(),
+            inner.queue.len(),
+            inner.current_game.len(),
+            inner.absent.len(),
+        );
+        match inner.state.clone() {
+            State::None => {
+                log::error!("Invalid queue state: None!");
+                *inner = Inner::new();
+            }
+            State::WaitingForPlayers(time) => {
+                inner.drop_absent();
+                let was_empty = inner.current_game.is_empty();
+                inner.matchmake(&self.game, self.required_players);
+                // * The wait for AI starts with the first matched player
+                let time = if was_empty && !inner.current_game.is_empty() {
+                    inner.matched = std::time::Instant::now();
+                    inner.state = State::WaitingForPlayers(inner.matched);
+                    inner.matched
+                } else {
+                    time
+                };
+                if !inner.current_game.is_empty()
+                    && (inner.current_game.len() >= self.required_players
+                        || Some(time.elapsed()) >= self.wait_time)
+                {
+                    inner.state = if self.ready_check.is_some() {
+                        State::ReadyCheck(std::time::Instant::now())
                     } else {
-                        queue.queue.push((uid.clone(), name.clone()));
-                        true
+                        State::Playing
+                    };
+                }
+            }
+            State::ReadyCheck(time) => match self.ready_check {
+                Some(ready_check) => inner.check_ready(time, ready_check),
+                None => inner.state = State::Playing,
+            },
+            State::Playing => {
+                if inner.match_started.is_none() {
+                    inner.match_started = Some(std::time::Instant::now());
+                }
+            }
+            State::Finished(_) => (),
+        }
+        let after = (
+            inner.state.clone(),
+            inner.queue.len(),
+            inner.current_game.len(),
+            inner.absent.len(),
+        );
+        if after != before {
+            self.notify();
+        }
+
+        // * Absent players are only dropped while waiting for players
+        let grace = std::time::Duration::from_secs(config().queue.reconnect_grace);
+        let absent = matches!(inner.state, State::WaitingForPlayers(_))
+            .then(|| inner.absent.values().map(|since| *since + grace).min())
+            .flatten();
+        let timer = match inner.state {
+            State::WaitingForPlayers(time) if !inner.current_game.is_empty() => {
+                // * The rating gap keeps growing while players wait
+                let growing = (inner.current_game.len() < self.required_players
+                    && !inner.queue.is_empty())
+                .then(|| std::time::Instant::now() + std::time::Duration::from_secs(1));
+                self.wait_time
+                    .map(|wait_time| time + wait_time)
+                    .into_iter()
+                    .chain(growing)
+                    .min()
+            }
+            State::ReadyCheck(time) => self
+                .ready_check
+                .map(|ready_check| time + ready_check.timeout),
+            _ => None,
+        };
+        absent.into_iter().chain(timer).min()
+    }
+
+    /// Start matching players and make the websocket handler players join this queue with.
+    /// Players reconnecting during their match are handed back to it as [`Session::Resumed`].
+    /// Players are checked with [`moderation::admit`] first, the game keeps their
+    /// [`moderation::Connection`] for as long as it uses the socket.
+    /// Must be called from the server runtime
+    pub fn handler<F>(
+        &self,
+        player: &'static F,
+    ) -> impl Fn(String, Option<std::net::IpAddr>, WebSocket) -> Pin<Box<dyn Future<Output = ()> + Send>>
+    where
+        F: Fn(
+                String,
+                String,
+                Session,
+                moderation::Connection,
+                SplitSink<WebSocket, Message>,
+                SplitStream<WebSocket>,
+            ) + Sync
+            + Send,
+    {
+        self.restart();
+        let queue = self.clone();
+        spawn_in_server_runtime(async move {
+            let mut changes = queue.changes.subscribe();
+            loop {
+                let wake = queue.step();
+                // * Sleeps until something changes, or a timer of the current state runs out
+                tokio::select! {
+                    _ = changes.changed() => (),
+                    _ = async {
+                        match wake {
+                            Some(wake) => tokio::time::sleep_until(wake.into()).await,
+                            None => std::future::pending().await,
+                        }
+                    } => (),
+                }
+            }
+        });
+
+        let queue = self.clone();
+        move |uid: String, ip: Option<std::net::IpAddr>, websocket: WebSocket| {
+            let queue = queue.clone();
+            Box::pin(async move {
+                use futures_util::stream::StreamExt;
+                use futures_util::SinkExt;
+                let (mut tx, mut rx) = websocket.split();
+
+                macro_rules! try_send {
+                    ($message: expr) => {
+                        try_log!("Send error: {}"; tx.send($message).await)
+                    };
+                }
+
+                // * Get meta
+                let name = {
+                    // * The user might have changed their name since the last game
+                    auth::invalidate_user_profile(&uid);
+                    get_user_profile(&uid)
+                        .await
+                        .and_then(|user| user.display_name.map(|name| format!("@{name}")))
+                        .unwrap_or("Someone".to_owned())
+                };
+                let language = i18n::user_language(&uid);
+
+                // * Handed to the game with the socket, held until the player leaves
+                let mut guard = match moderation::admit(&uid, ip).await {
+                    Ok(connection) => connection,
+                    Err(denied) => {
+                        log::info!("{name} was denied: {denied:?}");
+                        try_send!(Message::text(format!("!{}", denied.message(&language))));
+                        return;
                     }
                 };
-                if !success {
-                    try_send!(Message::text("!Queue is full, try again later."));
+
+                // * Back into a running match
+                let resumed = queue.update(|inner| {
+                    let resumed = inner.state == State::Playing
+                        && inner.current_game.iter().any(|(user, _)| user == &uid);
+                    if resumed {
+                        inner.connect(&uid);
+                    }
+                    resumed
+                });
+                if resumed {
+                    log::info!("{name} reconnected!");
+                    try_send!(Message::text(tr(&language, "queue-resumed", &[])));
+                    player(uid, name, Session::Resumed, guard, tx, rx);
                     return;
                 }
-            }
 
-            // * Wait in the queue
-            loop {
-                #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-                enum UserState {
-                    InQueue(usize),
-                    InGame,
-                    Lost,
-                }
-
-                let state = {
-                    let queue = QUEUE.lock().unwrap();
-                    if queue.current_game.iter().any(|(user, _)| user == &uid) {
-                        UserState::InGame
-                    } else if let Some(position) =
-                        queue.queue.iter().position(|(user, _)| user == &uid)
+                // * Join the queue
+                let connection = queue.update(|inner| {
+                    // * Players of a finished match join again like everyone else
+                    if inner.queue.iter().any(|waiting| waiting.uid == uid)
+                        || inner.is_matched(&uid)
                     {
-                        UserState::InQueue(position + 1)
+                        // * Reconnected, keeps their place
+                        Some(inner.connect(&uid))
+                    } else if inner.queue.len() > queue.max_queue_size {
+                        None
                     } else {
-                        UserState::Lost
+                        inner.queue.push(Waiting {
+                            uid: uid.clone(),
+                            name: name.clone(),
+                            joined: std::time::Instant::now(),
+                            room: None,
+                        });
+                        Some(inner.connect(&uid))
                     }
+                });
+                let Some(connection) = connection else {
+                    try_send!(Message::text(format!(
+                        "!{}",
+                        tr(&language, "queue-full", &[])
+                    )));
+                    return;
                 };
 
-                match state {
-                    UserState::InQueue(position) => {
-                        if position_in_queue != position {
-                            try_send!(Message::text(format!("Position in queue: {position}")));
-                            position_in_queue = position;
+                // * Wait for a change of the queue while watching the connection,
+                // * leaves the queue if it's gone. `tick` wakes up early for countdowns
+                let mut changes = queue.changes.subscribe();
+                let mut last_seen = std::time::Instant::now();
+                let mut last_ping = std::time::Instant::now();
+                macro_rules! wait {
+                    ($tick: expr) => {
+                        let heartbeat_timeout =
+                            std::time::Duration::from_secs(config().queue.heartbeat_timeout);
+                        if last_ping.elapsed() >= heartbeat_timeout / 3 {
+                            try_send!(Message::ping(Vec::new()));
+                            last_ping = std::time::Instant::now();
                         }
+                        let timeout = (last_ping + heartbeat_timeout / 3)
+                            .min(last_seen + heartbeat_timeout)
+                            .min(std::time::Instant::now() + $tick);
+                        // * Incoming messages are handled right here, the status is only
+                        // * rebuilt on changes and ticks. Commands change the queue, which wakes it
+                        let closed = loop {
+                            tokio::select! {
+                                message = rx.next() => match message {
+                                    Some(Ok(message)) => {
+                                        last_seen = std::time::Instant::now();
+                                        let command = message.to_str().unwrap_or_default();
+                                        if command == "Ready" {
+                                            queue.update(|inner| inner.confirm_ready(&uid));
+                                        } else if let Some(code) = command.strip_prefix("Room") {
+                                            let code = code.trim();
+                                            let joined = queue.update(|inner| {
+                                                inner.join_room(
+                                                    &uid,
+                                                    (!code.is_empty()).then_some(code),
+                                                    queue.required_players,
+                                                )
+                                            });
+                                            if let Err(error) = joined {
+                                                try_send!(Message::text(tr(&language, error, &[])));
+                                            }
+                                        }
+                                    }
+                                    _ => break true,
+                                },
+                                _ = changes.changed() => break false,
+                                ban = guard.kicked() => {
+                                    log::info!("{name} was kicked");
+                                    try_send!(Message::text(format!(
+                                        "!{}",
+                                        moderation::Denied::Banned(ban).message(&language)
+                                    )));
+                                    return;
+                                }
+                                _ = tokio::time::sleep_until(timeout.into()) => break false,
+                            }
+                        };
+                        if closed || last_seen.elapsed() >= heartbeat_timeout {
+                            log::info!("{name} disconnected while waiting");
+                            queue.update(|inner| inner.disconnect(&uid, connection));
+                            return;
+                        }
+                    };
+                }
+
+                // * Wait in the queue and for the match to start
+                let mut last_message = None;
+                loop {
+                    let message = {
+                        let mut inner = queue.inner.lock().unwrap();
+                        changes.borrow_and_update();
+                        if inner.connections.get(&uid) != Some(&connection) {
+                            return;
+                        } else if let Some(ban) = guard.is_kicked() {
+                            format!("!{}", moderation::Denied::Banned(ban).message(&language))
+                        } else if inner.is_matched(&uid) {
+                            match inner.state {
+                                State::ReadyCheck(since) if !inner.ready.contains(&uid) => {
+                                    let time_left = queue.ready_check.map_or(
+                                        std::time::Duration::ZERO,
+                                        |ready_check| {
+                                            ready_check.timeout.saturating_sub(since.elapsed())
+                                        },
+                                    );
+                                    // * Controllers show a confirm button for `?` messages
+                                    format!(
+                                        "?{}",
+                                        tr(
+                                            &language,
+                                            "queue-ready-check",
+                                            &[("seconds", &time_left.as_secs())]
+                                        )
+                                    )
+                                }
+                                State::ReadyCheck(_) => tr(&language, "queue-ready", &[]),
+                                State::Playing => break,
+                                _ => tr(&language, "queue-waiting", &[]),
+                            }
+                        } else if let Some(waiting) =
+                            inner.queue.iter().find(|waiting| waiting.uid == uid)
+                        {
+                            // * Rooms wait in line as a unit, at the place of their first member
+                            let position = inner
+                                .queue
+                                .iter()
+                                .position(|other| {
+                                    other.uid == uid
+                                        || (waiting.room.is_some() && other.room == waiting.room)
+                                })
+                                .unwrap_or_default()
+                                + 1;
+                            match &waiting.room {
+                                Some(room) => tr(
+                                    &language,
+                                    "queue-room",
+                                    &[
+                                        ("code", room),
+                                        (
+                                            "players",
+                                            &inner
+                                                .queue
+                                                .iter()
+                                                .filter(|other| other.room == waiting.room)
+                                                .count(),
+                                        ),
+                                        ("position", &position),
+                                    ],
+                                ),
+                                None => tr(&language, "queue-position", &[("position", &position)]),
+                            }
+                        } else if inner.not_ready.remove(&uid) {
+                            format!("!{}", tr(&language, "queue-not-ready", &[]))
+                        } else {
+                            format!("!{}", tr(&language, "queue-lost", &[]))
+                        }
+                    };
+
+                    if last_message.as_ref() != Some(&message) {
+                        try_send!(Message::text(message.clone()));
+                        if message.starts_with('!') {
+                            return;
+                        }
+                        last_message = Some(message);
                     }
-                    UserState::InGame => {
-                        try_send!(Message::text("Waiting for players..."));
-                        break;
-                    }
-                    UserState::Lost => {
-                        try_send!(Message::text(
-                            "!Something went horribly wrong, we lost you in our queues!",
-                        ));
-                        return;
-                    }
+
+                    // * The ready check counts down every second
+                    let tick = if last_message
+                        .as_ref()
+                        .is_some_and(|message| message.starts_with('?'))
+                    {
+                        std::time::Duration::from_secs(1)
+                    } else {
+                        std::time::Duration::from_secs(config().queue.heartbeat_timeout)
+                    };
+                    wait!(tick);
                 }
 
-                tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
-            }
+                log::info!("{name} joined!");
+                try_send!(Message::text(tr(&language, "queue-joined", &[])));
 
-            log::info!("{name} joined!");
+                player(uid, name, Session::Joined, guard, tx, rx);
+            })
+        }
+    }
+}
 
-            // * Wait for other players
-            while {
-                let queue = QUEUE.lock().unwrap();
-                matches!(queue.state, State::WaitingForPlayers(_))
-            } {
-                tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
 
-            try_send!(Message::text("You're in!"));
+    /// A queue of players that joined `waited` seconds ago, each test uses its own game
+    fn queue(game: &str, players: &[(&str, f64, u64)]) -> Inner {
+        let mut queue = Inner::new();
+        for &(uid, rating, waited) in players {
+            rating::set_rating(game, uid, rating);
+            queue.queue.push(Waiting {
+                uid: uid.to_owned(),
+                name: uid.to_owned(),
+                joined: Instant::now() - Duration::from_secs(waited),
+                room: None,
+            });
+        }
+        queue
+    }
 
-            player(uid, name, tx, rx);
-        })
+    fn uids(players: &[(String, String)]) -> Vec<&str> {
+        players.iter().map(|(uid, _)| uid.as_str()).collect()
+    }
+
+    #[test]
+    fn longest_waiting_player_anchors_the_game() {
+        let mut queue = queue(
+            "anchor",
+            &[
+                ("first", 1000.0, 5),
+                ("second", 2000.0, 3),
+                ("third", 2000.0, 1),
+            ],
+        );
+        queue.matchmake("anchor", 2);
+        assert_eq!(uids(&queue.current_game), ["first"]);
+        assert_eq!(queue.anchor.map(|(rating, _)| rating), Some(1000.0));
+        assert_eq!(queue.queue.len(), 2);
+    }
+
+    #[test]
+    fn matches_players_within_the_gap() {
+        let gap = config().queue.rating_gap;
+        let mut queue = queue(
+            "gap",
+            &[
+                ("anchor", 1500.0, 0),
+                ("far", 1500.0 + gap * 2.0, 0),
+                ("close", 1500.0 - gap / 2.0, 0),
+            ],
+        );
+        queue.matchmake("gap", 2);
+        assert_eq!(uids(&queue.current_game), ["anchor", "close"]);
+        assert_eq!(queue.queue.len(), 1);
+    }
+
+    #[test]
+    fn gap_widens_while_the_anchor_waits() {
+        let distance = config().queue.rating_gap + config().queue.gap_growth * 10.0;
+        let mut fresh = queue(
+            "widening",
+            &[("anchor", 1500.0, 0), ("far", 1500.0 + distance, 0)],
+        );
+        fresh.matchmake("widening", 2);
+        assert_eq!(uids(&fresh.current_game), ["anchor"]);
+
+        let mut waited = queue(
+            "widening",
+            &[("anchor", 1500.0, 20), ("far", 1500.0 + distance, 0)],
+        );
+        waited.matchmake("widening", 2);
+        assert_eq!(uids(&waited.current_game), ["anchor", "far"]);
+    }
+
+    #[test]
+    fn stops_at_the_required_players() {
+        let mut queue = queue(
+            "full",
+            &[("a", 1500.0, 3), ("b", 1500.0, 2), ("c", 1500.0, 1)],
+        );
+        queue.matchmake("full", 2);
+        assert_eq!(uids(&queue.current_game), ["a", "b"]);
+        assert_eq!(queue.queue.len(), 1);
+    }
+
+    #[test]
+    fn finished_matches_hold_nobody() {
+        let mut queue = queue("finished", &[("a", 1500.0, 1), ("b", 1500.0, 0)]);
+        queue.matchmake("finished", 2);
+        assert!(queue.is_matched("a"));
+
+        queue.state = State::Finished(Instant::now());
+        assert!(!queue.is_matched("a"));
+        queue.restart();
+        assert!(!queue.is_matched("a"));
+    }
+
+    /// A queue whose match of `a` and `b` waits for them to confirm
+    fn ready_check(game: &str) -> Inner {
+        let mut queue = queue(game, &[("a", 1500.0, 1), ("b", 1500.0, 0)]);
+        queue.matchmake(game, 2);
+        queue.state = State::ReadyCheck(Instant::now());
+        queue
+    }
+
+    const CHECK: ReadyCheck = ReadyCheck {
+        timeout: Duration::from_secs(15),
+        requeue: true,
+    };
+
+    /// Since when a check ran out of time
+    fn expired(ready_check: ReadyCheck) -> Instant {
+        Instant::now() - ready_check.timeout - Duration::from_secs(1)
+    }
+
+    #[test]
+    fn ready_counts_only_during_the_check() {
+        let mut queue = queue("confirm", &[("a", 1500.0, 1), ("b", 1500.0, 0)]);
+        queue.matchmake("confirm", 2);
+        queue.confirm_ready("a");
+        assert!(queue.ready.is_empty());
+
+        queue.state = State::ReadyCheck(Instant::now());
+        queue.confirm_ready("stranger");
+        queue.confirm_ready("a");
+        assert_eq!(queue.ready.iter().collect::<Vec<_>>(), ["a"]);
+    }
+
+    #[test]
+    fn starts_once_everyone_is_ready() {
+        let mut queue = ready_check("all-ready");
+        queue.confirm_ready("a");
+        queue.check_ready(Instant::now(), CHECK);
+        assert!(matches!(queue.state, State::ReadyCheck(_)));
+
+        queue.confirm_ready("b");
+        queue.check_ready(Instant::now(), CHECK);
+        assert_eq!(queue.state, State::Playing);
+        assert!(queue.ready.is_empty());
+    }
+
+    #[test]
+    fn waits_until_the_timeout() {
+        let mut queue = ready_check("patience");
+        queue.check_ready(Instant::now() - CHECK.timeout / 2, CHECK);
+        assert!(matches!(queue.state, State::ReadyCheck(_)));
+        assert_eq!(uids(&queue.current_game), ["a", "b"]);
+    }
+
+    #[test]
+    fn requeues_idle_players_after_the_timeout() {
+        let mut queue = ready_check("requeue");
+        queue.confirm_ready("a");
+        queue.check_ready(expired(CHECK), CHECK);
+        assert!(matches!(queue.state, State::WaitingForPlayers(_)));
+        assert_eq!(uids(&queue.current_game), ["a"]);
+        assert_eq!(queue.queue.len(), 1);
+        assert!(queue.barred.contains("b"));
+        assert!(queue.not_ready.is_empty());
+    }
+
+    #[test]
+    fn drops_idle_players_without_requeue() {
+        let mut queue = ready_check("drop");
+        let check = ReadyCheck {
+            requeue: false,
+            ..CHECK
+        };
+        queue.check_ready(expired(check), check);
+        assert!(queue.current_game.is_empty());
+        assert!(queue.queue.is_empty());
+        assert_eq!(queue.not_ready.len(), 2);
+    }
+
+    #[test]
+    fn drops_players_that_keep_missing_the_check() {
+        let mut queue = ready_check("missing");
+        for _ in 1..MAX_MISSED_READY_CHECKS {
+            queue.check_ready(expired(CHECK), CHECK);
+            assert!(queue.not_ready.is_empty());
+            queue.matchmake("missing", 2);
+            queue.state = State::ReadyCheck(Instant::now());
+        }
+        queue.check_ready(expired(CHECK), CHECK);
+        assert_eq!(queue.not_ready.len(), 2);
+        assert!(queue.missed.is_empty());
+    }
+
+    #[test]
+    fn rooms_hold_at_most_a_match() {
+        let mut queue = queue(
+            "rooms",
+            &[
+                ("host", 1500.0, 3),
+                ("guest", 1500.0, 2),
+                ("late", 1500.0, 1),
+            ],
+        );
+        assert_eq!(queue.join_room("host", None, 2), Ok(()));
+        let code = queue.queue[0].room.clone().unwrap();
+
+        assert_eq!(queue.join_room("guest", Some(&code), 2), Ok(()));
+        assert_eq!(
+            queue.join_room("late", Some(&code), 2),
+            Err("queue-room-full")
+        );
+        assert_eq!(queue.queue[2].room, None);
+        // * Members rejoining their own room don't count against it
+        assert_eq!(queue.join_room("guest", Some(&code), 2), Ok(()));
+    }
+
+    #[test]
+    fn rooms_must_exist() {
+        let mut queue = queue("no-room", &[("a", 1500.0, 0)]);
+        assert_eq!(queue.join_room("a", Some("NOPE"), 2), Err("queue-no-room"));
+        assert_eq!(queue.queue[0].room, None);
     }
 }
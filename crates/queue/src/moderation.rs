@@ -0,0 +1,387 @@
+use scheduler::{config::Role, *};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ban {
+    pub reason: Option<String>,
+    /// Unix timestamp in seconds the timeout ends at, bans without one are permanent
+    pub until: Option<u64>,
+}
+
+impl Ban {
+    fn active(&self) -> bool {
+        self.until.map_or(true, |until| until > now())
+    }
+}
+
+/// Why a player can't join the queue
+#[derive(Clone, Debug)]
+pub enum Denied {
+    Banned(Ban),
+    TooManyConnections,
+    RateLimited,
+}
+
+impl Denied {
+    pub fn message(&self, language: &str) -> String {
+        match self {
+            Self::Banned(ban) => {
+                let reason = ban
+                    .reason
+                    .clone()
+                    .unwrap_or_else(|| tr(language, "moderation-no-reason", &[]));
+                match ban.until {
+                    Some(until) => tr(
+                        language,
+                        "moderation-timeout",
+                        &[
+                            ("minutes", &until.saturating_sub(now()).div_ceil(60)),
+                            ("reason", &reason),
+                        ],
+                    ),
+                    None => tr(language, "moderation-banned", &[("reason", &reason)]),
+                }
+            }
+            Self::TooManyConnections => tr(language, "moderation-connections", &[]),
+            Self::RateLimited => tr(language, "moderation-rate-limit", &[]),
+        }
+    }
+}
+
+fn now() -> u64 {
+    std::time::UNIX_EPOCH
+        .elapsed()
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// * ---------------------------------- Bans ---------------------------------- * //
+/// Timeouts, and the reasons of permanent bans, by uid.
+/// Whether a player is banned for good is only decided by their [`Role::Banned`]
+type Bans = BTreeMap<String, Ban>;
+
+/// The bans along with the modification time of the file they were read from
+static BANS: Mutex<Option<(Option<SystemTime>, Bans)>> = Mutex::new(None);
+
+fn file_modified() -> Option<SystemTime> {
+    std::fs::metadata(&config().queue.moderation)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Every game keeps its own copy, so the file is read again whenever it changes
+fn with_bans<T>(f: impl FnOnce(&mut Bans) -> T) -> T {
+    let mut cache = BANS.lock().unwrap();
+    let modified = file_modified();
+    if !cache
+        .as_ref()
+        .is_some_and(|(cached, _)| *cached == modified)
+    {
+        let path = &config().queue.moderation;
+        let bans = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|data| {
+                log_error!(
+                    "Failed to parse bans from {}: {}!", path;
+                    serde_json::from_str(&data)
+                )
+            })
+            .unwrap_or_default();
+        *cache = Some((modified, bans));
+    }
+
+    f(&mut cache.as_mut().unwrap().1)
+}
+
+fn save(bans: &Bans) -> Option<()> {
+    let path = std::path::Path::new(&config().queue.moderation);
+    if let Some(parent) = path.parent() {
+        log_error!("Failed to create {}: {}!", parent.display(); std::fs::create_dir_all(parent))?;
+    }
+    let json = log_error!("Failed to serialize bans: {}!"; serde_json::to_string_pretty(bans))?;
+    log_error!("Failed to save bans to {}: {}!", path.display(); std::fs::write(path, json))
+}
+
+async fn has_banned_role(uid: &str) -> bool {
+    points::store::store()
+        .await
+        .user(uid)
+        .await
+        .is_some_and(|user| user.roles.contains(&Role::Banned))
+}
+
+/// Ban a player, for `minutes` if set or until unbanned, and kick them out of every queue and game
+pub async fn ban(uid: &str, minutes: Option<u64>, reason: Option<String>) -> Option<()> {
+    let ban = Ban {
+        reason,
+        until: minutes.map(|minutes| now() + minutes * 60),
+    };
+    if ban.until.is_none() {
+        let mut roles = points::store::store()
+            .await
+            .user(uid)
+            .await
+            .unwrap_or_default()
+            .roles;
+        roles.insert(Role::Banned);
+        points::set_roles(uid, roles).await?;
+    }
+    with_bans(|bans| {
+        bans.insert(uid.to_owned(), ban.clone());
+        save(bans)
+    })?;
+    kick(uid, ban);
+    Some(())
+}
+
+/// Lift a ban or timeout, returns false if the player wasn't banned
+pub async fn unban(uid: &str) -> bool {
+    let timed_out = with_bans(|bans| {
+        let removed = bans.remove(uid);
+        if removed.is_some() {
+            save(bans);
+        }
+        removed.is_some_and(|ban| ban.until.is_some() && ban.active())
+    });
+    let mut roles = points::store::store()
+        .await
+        .user(uid)
+        .await
+        .unwrap_or_default()
+        .roles;
+    let banned = roles.remove(&Role::Banned) && points::set_roles(uid, roles).await.is_some();
+    timed_out || banned
+}
+
+pub async fn banned(uid: &str) -> Option<Ban> {
+    let ban = with_bans(|bans| bans.get(uid).cloned());
+    if let Some(ban) = ban
+        .as_ref()
+        .filter(|ban| ban.until.is_some() && ban.active())
+    {
+        return Some(ban.clone());
+    }
+    has_banned_role(uid).await.then(|| Ban {
+        reason: ban.and_then(|ban| ban.reason),
+        until: None,
+    })
+}
+
+// * ------------------------------- Connections ------------------------------- * //
+#[derive(Default)]
+struct Limits {
+    uids: BTreeMap<String, usize>,
+    ips: BTreeMap<IpAddr, usize>,
+    /// Recent queue joins of every uid
+    joins: BTreeMap<String, VecDeque<std::time::Instant>>,
+    /// Uid and kick signal of every open connection by id
+    kicks: BTreeMap<u64, (String, tokio::sync::watch::Sender<Option<Ban>>)>,
+}
+
+static LIMITS: Mutex<Option<Limits>> = Mutex::new(None);
+
+/// Queues remove kicked players with these
+type Kicker = Box<dyn Fn(&str) + Send>;
+
+/// By queue id, so a queue made again on reload replaces its old kicker
+static KICKERS: Mutex<BTreeMap<&'static str, Kicker>> = Mutex::new(BTreeMap::new());
+
+pub(crate) fn on_kick(queue: &'static str, kicker: impl Fn(&str) + Send + 'static) {
+    KICKERS.lock().unwrap().insert(queue, Box::new(kicker));
+}
+
+/// Signal every connection of a banned player and take them out of every queue
+fn kick(uid: &str, ban: Ban) {
+    // * Connections first, so waiting players are told why they left the queue
+    if let Some(limits) = LIMITS.lock().unwrap().as_ref() {
+        for (_, kicked) in limits.kicks.values().filter(|(other, _)| other == uid) {
+            kicked.send_replace(Some(ban.clone()));
+        }
+    }
+    for kicker in KICKERS.lock().unwrap().values() {
+        kicker(uid);
+    }
+}
+
+/// Counts towards the connection limits until dropped
+pub struct Connection {
+    id: u64,
+    uid: String,
+    ip: Option<IpAddr>,
+    kicked: tokio::sync::watch::Receiver<Option<Ban>>,
+}
+
+impl Connection {
+    /// Resolves once a moderator bans the player, the connection should be closed then
+    pub async fn kicked(&mut self) -> Ban {
+        let ban = self
+            .kicked
+            .wait_for(Option::is_some)
+            .await
+            .map(|ban| ban.clone());
+        match ban {
+            Ok(Some(ban)) => ban,
+            // * The sender lives as long as the connection, so this can't happen
+            _ => std::future::pending().await,
+        }
+    }
+
+    /// The ban if the player was kicked already
+    pub fn is_kicked(&self) -> Option<Ban> {
+        self.kicked.borrow().clone()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut limits = LIMITS.lock().unwrap();
+        let limits = limits.get_or_insert_with(Limits::default);
+        limits.kicks.remove(&self.id);
+        if let Some(count) = limits.uids.get_mut(&self.uid) {
+            *count -= 1;
+            if *count == 0 {
+                limits.uids.remove(&self.uid);
+            }
+        }
+        if let Some(ip) = self.ip {
+            if let Some(count) = limits.ips.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    limits.ips.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// Check a player that wants to join a queue against bans, connection caps and the join rate
+pub async fn admit(uid: &str, ip: Option<IpAddr>) -> Result<Connection, Denied> {
+    if let Some(ban) = banned(uid).await {
+        return Err(Denied::Banned(ban));
+    }
+
+    let config = &config().queue;
+    let mut limits = LIMITS.lock().unwrap();
+    let limits = limits.get_or_insert_with(Limits::default);
+    if limits.uids.get(uid).copied().unwrap_or_default() >= config.max_connections_per_uid
+        || ip.is_some_and(|ip| {
+            limits.ips.get(&ip).copied().unwrap_or_default() >= config.max_connections_per_ip
+        })
+    {
+        return Err(Denied::TooManyConnections);
+    }
+
+    let window = std::time::Duration::from_secs(config.join_window);
+    let joins = limits.joins.entry(uid.to_owned()).or_default();
+    while joins.front().is_some_and(|join| join.elapsed() > window) {
+        joins.pop_front();
+    }
+    if joins.len() >= config.join_limit {
+        return Err(Denied::RateLimited);
+    }
+    joins.push_back(std::time::Instant::now());
+    limits.joins.retain(|_, joins| !joins.is_empty());
+
+    *limits.uids.entry(uid.to_owned()).or_default() += 1;
+    if let Some(ip) = ip {
+        *limits.ips.entry(ip).or_default() += 1;
+    }
+    static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+    let id = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    let (kick, kicked) = tokio::sync::watch::channel(None);
+    limits.kicks.insert(id, (uid.to_owned(), kick));
+    Ok(Connection {
+        id,
+        uid: uid.to_owned(),
+        ip,
+        kicked,
+    })
+}
+
+/// Moderator commands, returns false if the command is not a moderation command
+pub fn command(command: &str) -> bool {
+    fn reason(args: std::str::SplitWhitespace) -> Option<String> {
+        let reason = args.collect::<Vec<_>>().join(" ");
+        (!reason.is_empty()).then_some(reason)
+    }
+
+    let mut args = command.split_whitespace();
+    let cmd = args.next().unwrap_or_default();
+    let uid = args.next().map(str::to_owned);
+    match cmd {
+        "ban" => {
+            let Some(uid) = uid else {
+                log::error!("Usage: ban <uid> [reason]");
+                return true;
+            };
+            let reason = reason(args);
+            spawn_in_server_runtime(async move {
+                if ban(&uid, None, reason).await.is_some() {
+                    log::info!("Banned {uid}");
+                }
+            });
+        }
+        "timeout" => {
+            let (Some(uid), Some(Ok(minutes))) = (uid, args.next().map(str::parse::<u64>)) else {
+                log::error!("Usage: timeout <uid> <minutes> [reason]");
+                return true;
+            };
+            let reason = reason(args);
+            spawn_in_server_runtime(async move {
+                if ban(&uid, Some(minutes), reason).await.is_some() {
+                    log::info!("Timed out {uid} for {minutes} minutes");
+                }
+            });
+        }
+        "unban" => {
+            let Some(uid) = uid else {
+                log::error!("Usage: unban <uid>");
+                return true;
+            };
+            spawn_in_server_runtime(async move {
+                if unban(&uid).await {
+                    log::info!("Unbanned {uid}");
+                } else {
+                    log::error!("{uid} is not banned!");
+                }
+            });
+        }
+        "bans" => {
+            spawn_in_server_runtime(async {
+                let bans = with_bans(|bans| bans.clone());
+                for (uid, ban) in bans
+                    .iter()
+                    .filter(|(_, ban)| ban.until.is_some() && ban.active())
+                {
+                    let minutes = ban
+                        .until
+                        .unwrap_or_default()
+                        .saturating_sub(now())
+                        .div_ceil(60);
+                    let reason = ban.reason.as_deref().unwrap_or("no reason");
+                    log::info!("{uid} for {minutes} more minutes: {reason}");
+                }
+                for (uid, user) in points::store::store().await.users().await {
+                    if user.roles.contains(&Role::Banned) {
+                        let reason = bans
+                            .get(&uid)
+                            .and_then(|ban| ban.reason.as_deref())
+                            .unwrap_or("no reason");
+                        log::info!("{uid}: {reason}");
+                    }
+                }
+            });
+        }
+        _ => return false,
+    }
+    true
+}
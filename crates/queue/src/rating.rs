@@ -0,0 +1,215 @@
+use scheduler::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Matches after which a player's rating counts as settled
+const PROVISIONAL_GAMES: u32 = 10;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub games: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: config().queue.initial_rating,
+            games: 0,
+        }
+    }
+}
+
+/// Ratings by game and uid
+type Ratings = BTreeMap<String, BTreeMap<String, Rating>>;
+
+static RATINGS: Mutex<Option<Ratings>> = Mutex::new(None);
+
+fn with_ratings<T>(f: impl FnOnce(&mut Ratings) -> T) -> T {
+    let mut ratings = RATINGS.lock().unwrap();
+    f(ratings.get_or_insert_with(|| {
+        let path = &config().queue.ratings;
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|data| {
+                log_error!(
+                    "Failed to parse ratings from {}: {}!", path;
+                    serde_json::from_str(&data)
+                )
+            })
+            .unwrap_or_default()
+    }))
+}
+
+fn save(ratings: &Ratings) -> Option<()> {
+    let path = std::path::Path::new(&config().queue.ratings);
+    if let Some(parent) = path.parent() {
+        log_error!("Failed to create {}: {}!", parent.display(); std::fs::create_dir_all(parent))?;
+    }
+    let json =
+        log_error!("Failed to serialize ratings: {}!"; serde_json::to_string_pretty(ratings))?;
+    log_error!("Failed to save ratings to {}: {}!", path.display(); std::fs::write(path, json))
+}
+
+pub fn rating(game: &str, uid: &str) -> Rating {
+    with_ratings(|ratings| {
+        ratings
+            .get(game)
+            .and_then(|ratings| ratings.get(uid))
+            .copied()
+            .unwrap_or_default()
+    })
+}
+
+/// Update ratings from a finished match. Every pair of players counts as one Elo game,
+/// decided by their scores. AI players are left out
+pub fn report(game: &str, results: &[(String, f64)]) {
+    with_ratings(|ratings| {
+        if update(ratings.entry(game.to_owned()).or_default(), game, results) {
+            save(ratings);
+        }
+    });
+}
+
+/// The Elo update of [`report`] on the ratings of one game. Returns false if nobody was rated
+fn update(ratings: &mut BTreeMap<String, Rating>, game: &str, results: &[(String, f64)]) -> bool {
+    let results = results
+        .iter()
+        .filter(|(uid, _)| uid != "AI")
+        .collect::<Vec<_>>();
+    if results.len() < 2 {
+        return false;
+    }
+
+    let before = results
+        .iter()
+        .map(|(uid, _)| ratings.get(uid).copied().unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    for (index, (uid, score)) in results.iter().enumerate() {
+        let mut change = 0.0;
+        for (other_index, (_, other_score)) in results.iter().enumerate() {
+            if other_index == index {
+                continue;
+            }
+            let difference = before[other_index].rating - before[index].rating;
+            let expected = 1.0 / (1.0 + 10f64.powf(difference / 400.0));
+            let actual = match score.total_cmp(other_score) {
+                std::cmp::Ordering::Greater => 1.0,
+                std::cmp::Ordering::Equal => 0.5,
+                std::cmp::Ordering::Less => 0.0,
+            };
+            change += actual - expected;
+        }
+
+        // * New players move faster until their rating settles
+        let mut k = config().queue.k_factor / (results.len() - 1) as f64;
+        if before[index].games < PROVISIONAL_GAMES {
+            k *= 2.0;
+        }
+        let rating = ratings.entry(uid.clone()).or_default();
+        rating.rating = before[index].rating + k * change;
+        rating.games += 1;
+        log::info!("{uid} is now rated {:.0} in {game}", rating.rating);
+    }
+    true
+}
+
+/// Rate a player without touching the ratings file
+#[cfg(test)]
+pub(crate) fn set_rating(game: &str, uid: &str, rating: f64) {
+    with_ratings(|ratings| {
+        ratings
+            .entry(game.to_owned())
+            .or_default()
+            .insert(uid.to_owned(), Rating { rating, games: 0 });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rated(rating: f64, games: u32) -> Rating {
+        Rating { rating, games }
+    }
+
+    fn results(scores: &[(&str, f64)]) -> Vec<(String, f64)> {
+        scores
+            .iter()
+            .map(|&(uid, score)| (uid.to_owned(), score))
+            .collect()
+    }
+
+    #[test]
+    fn winner_takes_from_loser() {
+        let mut ratings = BTreeMap::from([
+            ("a".to_owned(), rated(1500.0, PROVISIONAL_GAMES)),
+            ("b".to_owned(), rated(1500.0, PROVISIONAL_GAMES)),
+        ]);
+        assert!(update(
+            &mut ratings,
+            "test",
+            &results(&[("a", 2.0), ("b", 1.0)])
+        ));
+
+        let k = config().queue.k_factor;
+        assert_eq!(ratings["a"].rating, 1500.0 + k / 2.0);
+        assert_eq!(ratings["b"].rating, 1500.0 - k / 2.0);
+        assert_eq!(ratings["a"].games, PROVISIONAL_GAMES + 1);
+    }
+
+    #[test]
+    fn draws_move_towards_each_other() {
+        let mut ratings = BTreeMap::from([
+            ("a".to_owned(), rated(1700.0, PROVISIONAL_GAMES)),
+            ("b".to_owned(), rated(1300.0, PROVISIONAL_GAMES)),
+        ]);
+        update(&mut ratings, "test", &results(&[("a", 1.0), ("b", 1.0)]));
+        assert!(ratings["a"].rating < 1700.0);
+        assert!(ratings["b"].rating > 1300.0);
+        assert!((ratings["a"].rating + ratings["b"].rating - 3000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn upsets_move_more_than_expected_wins() {
+        let mut favorite_won = BTreeMap::from([
+            ("favorite".to_owned(), rated(1700.0, PROVISIONAL_GAMES)),
+            ("underdog".to_owned(), rated(1300.0, PROVISIONAL_GAMES)),
+        ]);
+        let mut underdog_won = favorite_won.clone();
+        update(
+            &mut favorite_won,
+            "test",
+            &results(&[("favorite", 1.0), ("underdog", 0.0)]),
+        );
+        update(
+            &mut underdog_won,
+            "test",
+            &results(&[("favorite", 0.0), ("underdog", 1.0)]),
+        );
+        assert!(
+            underdog_won["underdog"].rating - 1300.0 > favorite_won["favorite"].rating - 1700.0
+        );
+    }
+
+    #[test]
+    fn new_players_move_twice_as_fast() {
+        let mut ratings = BTreeMap::new();
+        update(&mut ratings, "test", &results(&[("a", 1.0), ("b", 0.0)]));
+        let initial = config().queue.initial_rating;
+        assert_eq!(ratings["a"].rating, initial + config().queue.k_factor);
+        assert_eq!(ratings["a"].games, 1);
+    }
+
+    #[test]
+    fn ai_is_not_rated() {
+        let mut ratings = BTreeMap::new();
+        assert!(!update(
+            &mut ratings,
+            "test",
+            &results(&[("a", 1.0), ("AI", 0.0)])
+        ));
+        assert!(ratings.is_empty());
+    }
+}
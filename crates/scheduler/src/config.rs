@@ -0,0 +1,272 @@
+use super::*;
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub firebase: FirebaseConfig,
+    pub auth: AuthConfig,
+    pub points: PointsConfig,
+    pub leaderboard: LeaderboardConfig,
+    pub banner: BannerConfig,
+    pub theme: ThemeConfig,
+    pub i18n: I18nConfig,
+    pub queue: QueueConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FirebaseConfig {
+    pub project_id: String,
+    pub credentials: String,
+}
+
+impl Default for FirebaseConfig {
+    fn default() -> Self {
+        Self {
+            project_id: "infinitecoderwebsite".to_owned(),
+            credentials: "firebase-private.json".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthProviderKind {
+    #[default]
+    Firebase,
+    /// Profiles from [`AuthConfig::users`]. Only covers profiles: players still sign in
+    /// with Firebase in the browser, and their ID tokens are checked against Google's keys
+    /// unless [`AuthConfig::keys`] is set
+    Local,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub provider: AuthProviderKind,
+    /// User profiles for the local provider
+    pub users: String,
+    /// JWK set to verify ID tokens with instead of Google's public keys
+    pub keys: Option<String>,
+    /// How long user profiles are cached, in seconds
+    pub profile_ttl: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            provider: AuthProviderKind::default(),
+            users: "users.toml".to_owned(),
+            keys: None,
+            profile_ttl: 600,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PointsBackend {
+    #[default]
+    Firestore,
+    Sqlite,
+    Json,
+    Memory,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PointsConfig {
+    pub backend: PointsBackend,
+    /// Database file for the sqlite and json backends
+    pub path: Option<String>,
+    pub seasons: Vec<Season>,
+    /// Achievement definitions, built-in ones are used if the file doesn't exist
+    pub achievements: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Season {
+    pub id: String,
+    pub start: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub end: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// Share of every balance kept when the season is closed, 0 resets everyone
+    #[serde(default)]
+    pub carry_over: f64,
+}
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Staff,
+    Banned,
+    Hidden,
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "staff" => Ok(Self::Staff),
+            "banned" => Ok(Self::Banned),
+            "hidden" => Ok(Self::Hidden),
+            _ => Err(format!("Unknown role {s:?}!")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LeaderboardConfig {
+    /// Users with any of these roles are not shown on the leaderboard
+    pub exclude: Vec<Role>,
+    /// Users with any of these roles are shown below everyone else and can't be the leader
+    pub demote: Vec<Role>,
+    /// Uids given the staff role on startup if they don't have it yet
+    pub staff: Vec<String>,
+}
+
+impl Default for LeaderboardConfig {
+    fn default() -> Self {
+        Self {
+            exclude: vec![Role::Banned, Role::Hidden],
+            demote: vec![Role::Staff],
+            // * The host, who was kept off the top by a hardcoded uid before roles existed
+            staff: vec!["GiAIWs311JaKAWwTEkll5LLPKT63".to_owned()],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BannerConfig {
+    /// Bottom banner messages, reloaded when the file changes
+    pub messages: String,
+}
+
+impl Default for BannerConfig {
+    fn default() -> Self {
+        Self {
+            messages: "banner.toml".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// Fonts and colors of everything drawn on stream
+    pub path: String,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            path: "theme.toml".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct I18nConfig {
+    /// Used when nothing better is known
+    pub default: String,
+    /// Stream languages for schedule entries that don't set any
+    pub stream: Vec<String>,
+    /// Seconds before switching to the next stream language
+    pub alternate: u64,
+    /// Directory with additional `<language>.toml` message files
+    pub path: String,
+}
+
+impl Default for I18nConfig {
+    fn default() -> Self {
+        Self {
+            default: "en".to_owned(),
+            stream: vec!["en".to_owned()],
+            alternate: 60,
+            path: "locales".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// Per-game player ratings
+    pub ratings: String,
+    /// Rating of players who haven't played yet
+    pub initial_rating: f64,
+    /// Largest rating change from a single match
+    pub k_factor: f64,
+    /// Rating difference accepted between players right away
+    pub rating_gap: f64,
+    /// Added to the accepted rating difference for every second the first player waits
+    pub gap_growth: f64,
+    /// Seconds without any message before a waiting player counts as disconnected
+    pub heartbeat_timeout: u64,
+    /// Seconds a disconnected player keeps their place to reconnect
+    pub reconnect_grace: u64,
+    /// Bans and timeouts
+    pub moderation: String,
+    /// Connections waiting in queues at once from one account
+    pub max_connections_per_uid: usize,
+    /// Connections waiting in queues at once from one address
+    pub max_connections_per_ip: usize,
+    /// Queue joins allowed from one account within `join_window` seconds
+    pub join_limit: usize,
+    pub join_window: u64,
+    /// Proxies whose X-Forwarded-For header is believed, everyone else is limited by their own address
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            ratings: "state/ratings.json".to_owned(),
+            initial_rating: 1500.0,
+            k_factor: 32.0,
+            rating_gap: 200.0,
+            gap_growth: 20.0,
+            heartbeat_timeout: 10,
+            reconnect_grace: 15,
+            moderation: "state/moderation.json".to_owned(),
+            max_connections_per_uid: 3,
+            max_connections_per_ip: 8,
+            join_limit: 10,
+            join_window: 60,
+            trusted_proxies: vec![
+                std::net::Ipv4Addr::LOCALHOST.into(),
+                std::net::Ipv6Addr::LOCALHOST.into(),
+            ],
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Option<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return None;
+        }
+        Some(try_log!(
+            "Failed to parse config {}: {}!", path.display();
+            toml::from_str(&try_log!(
+                "Failed to load config {}: {}!", path.display();
+                std::fs::read_to_string(path)
+                => None
+            ))
+            => None
+        ))
+    }
+}
+
+static CONFIG: std::sync::OnceLock<Config> = std::sync::OnceLock::new();
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load("config.toml").unwrap_or_default())
+}
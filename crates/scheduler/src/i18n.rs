@@ -0,0 +1,182 @@
+use super::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+/// Catalogs shipped with the scheduler, files in the locales directory add to or override them
+const BUILTIN: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.toml")),
+    ("ru", include_str!("../locales/ru.toml")),
+];
+
+type Catalog = HashMap<String, HashMap<String, String>>;
+
+fn load_catalog() -> Catalog {
+    let mut catalog = Catalog::new();
+    for &(language, source) in BUILTIN {
+        let messages = try_log!(
+            "Invalid builtin messages for {}: {}!", language;
+            toml::from_str(source)
+            => catalog
+        );
+        catalog.insert(language.to_owned(), messages);
+    }
+
+    let path = &config().i18n.path;
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path
+                .extension()
+                .map_or(true, |extension| extension != "toml")
+            {
+                continue;
+            }
+            let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let Some(source) = log_error!(
+                "Failed to load messages {}: {}!", path.display();
+                std::fs::read_to_string(&path)
+            ) else {
+                continue;
+            };
+            let Some(messages) = log_error!(
+                "Failed to parse messages {}: {}!", path.display();
+                toml::from_str::<HashMap<String, String>>(&source)
+            ) else {
+                continue;
+            };
+            catalog
+                .entry(language.to_owned())
+                .or_default()
+                .extend(messages);
+        }
+    }
+    catalog
+}
+
+fn catalog() -> &'static Catalog {
+    static CATALOG: std::sync::OnceLock<Catalog> = std::sync::OnceLock::new();
+    CATALOG.get_or_init(load_catalog)
+}
+
+/// Languages with a message catalog
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    catalog().keys().map(String::as_str)
+}
+
+/// Look up a message and fill in its `{arg}` placeholders. Falls back to the default language
+/// and then to the key itself
+pub fn tr(language: &str, key: &str, args: &[(&str, &dyn std::fmt::Display)]) -> String {
+    let catalog = catalog();
+    let Some(message) = [language, config().i18n.default.as_str(), "en"]
+        .into_iter()
+        .find_map(|language| catalog.get(language)?.get(key))
+    else {
+        log::warn!("No message {key:?}!");
+        return key.to_owned();
+    };
+    args.iter().fold(message.clone(), |message, (name, value)| {
+        message.replace(&format!("{{{name}}}"), &value.to_string())
+    })
+}
+
+/// Pick the best known language from an `Accept-Language` header or a single language tag
+pub fn negotiate(accept_language: &str) -> Option<String> {
+    let mut languages = accept_language
+        .split(',')
+        .filter_map(|part| {
+            let mut parts = part.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(1.0, |quality| quality.parse::<f32>().unwrap_or(0.0));
+            Some((tag, quality))
+        })
+        .collect::<Vec<_>>();
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    languages.into_iter().find_map(|(tag, _)| {
+        let primary = tag.split(['-', '_']).next()?.to_lowercase();
+        catalog().contains_key(&primary).then_some(primary)
+    })
+}
+
+/// Text given in place instead of in the catalog, either for every language or per language
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Localized {
+    Any(String),
+    PerLanguage(HashMap<String, String>),
+}
+
+impl Localized {
+    pub fn get(&self, language: &str) -> &str {
+        match self {
+            Self::Any(text) => text,
+            Self::PerLanguage(texts) => texts
+                .get(language)
+                .or_else(|| texts.get(&config().i18n.default))
+                .or_else(|| texts.values().next())
+                .map_or("", String::as_str),
+        }
+    }
+
+    pub fn contains(&self, pattern: &str) -> bool {
+        match self {
+            Self::Any(text) => text.contains(pattern),
+            Self::PerLanguage(texts) => texts.values().any(|text| text.contains(pattern)),
+        }
+    }
+}
+
+// * ---------------------------------- Languages --------------------------------- * //
+static USER_LANGUAGES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+pub fn set_user_language(uid: &str, language: String) {
+    USER_LANGUAGES
+        .lock()
+        .unwrap()
+        .insert(uid.to_owned(), language);
+}
+
+/// Language the user connected with, for messages sent over their websocket
+pub fn user_language(uid: &str) -> String {
+    USER_LANGUAGES
+        .lock()
+        .unwrap()
+        .get(uid)
+        .cloned()
+        .unwrap_or_else(|| config().i18n.default.clone())
+}
+
+static STREAM_LANGUAGE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set_stream_language(language: &str) {
+    *STREAM_LANGUAGE.lock().unwrap() = Some(language.to_owned());
+}
+
+/// Language of everything drawn on stream
+pub fn stream_language() -> String {
+    STREAM_LANGUAGE
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| config().i18n.default.clone())
+}
+
+/// Current one of the given languages (or the configured stream languages, if there are none),
+/// switching every `alternate` seconds
+pub fn alternate(languages: &[String]) -> String {
+    let languages = if languages.is_empty() {
+        &config().i18n.stream
+    } else {
+        languages
+    };
+    if languages.is_empty() {
+        return config().i18n.default.clone();
+    }
+    let index = chrono::Utc::now().timestamp() / config().i18n.alternate.max(1) as i64;
+    languages[index as usize % languages.len()].clone()
+}
@@ -1,11 +1,21 @@
 #![feature(stmt_expr_attributes)]
+pub use auth::{get_user_profile, get_user_profiles, UserProfile};
 use batbox_la::*;
 pub use chrono::DateTime;
 pub use chrono::Duration;
+pub use config::config;
+pub use i18n::tr;
 use libloading::Library;
 use streamer::BackgroundController;
+pub use text::{ellipsize, fit_font_size, show_lines, text_center_offset, wrap_text, Marquee};
+pub use theme::theme;
 
+pub mod auth;
+pub mod config;
+pub mod i18n;
 pub mod streamer;
+pub mod text;
+pub mod theme;
 
 #[allow(improper_ctypes_definitions)]
 pub type PluginFrame = unsafe extern "C" fn(
@@ -18,13 +28,19 @@ pub type PluginFrame = unsafe extern "C" fn(
     bool,
 ) -> bool;
 
+pub type BoxFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + Send + 'a>>;
+
 #[allow(improper_ctypes_definitions)]
 pub type PluginCommand = unsafe extern "C" fn(&str);
 
+#[allow(improper_ctypes_definitions)]
+pub type PluginLanguage = unsafe extern "C" fn(&str);
+
 pub struct Plugin<'a> {
     pub library: Library,
     pub frame: libloading::Symbol<'a, PluginFrame>,
     pub command: Option<libloading::Symbol<'a, PluginCommand>>,
+    pub language: Option<libloading::Symbol<'a, PluginLanguage>>,
 }
 
 impl Plugin<'_> {
@@ -54,11 +70,15 @@ impl Plugin<'_> {
             let command = (*(&library as *const Library))
                 .get::<PluginCommand>(b"command")
                 .ok();
+            let language = (*(&library as *const Library))
+                .get::<PluginLanguage>(b"language")
+                .ok();
             load(args);
             Some(Self {
                 library,
                 frame,
                 command,
+                language,
             })
         }
     }
@@ -125,10 +145,11 @@ where
 
 // * Minimal server
 pub fn make_minimal_server<Leaderboard>(
-    leaderboard: &Leaderboard,
-) -> impl warp::Filter<Extract = impl warp::reply::Reply, Error = warp::reject::Rejection> + Clone + '_
+    leaderboard: Leaderboard,
+) -> impl warp::Filter<Extract = impl warp::reply::Reply, Error = warp::reject::Rejection> + Clone
 where
-    Leaderboard: Fn(Option<String>) -> warp::reply::Json + Sync,
+    Leaderboard: warp::Filter<Error = warp::reject::Rejection> + Clone + Send + Sync + 'static,
+    Leaderboard::Extract: warp::reply::Reply,
 {
     use warp::Filter;
 
@@ -141,26 +162,28 @@ where
     let routes = routes.or(warp::path("account").and(warp::fs::dir("./html/account/")));
     routes.or(warp::path("leaderboard").and(
         warp::path::path("api")
-            .and(
-                warp::path::param::<String>()
-                    .and(warp::path::end())
-                    .map(|uid| leaderboard(Some(uid)))
-                    .or(warp::path::end().map(|| leaderboard(None))),
-            )
+            .and(leaderboard)
             .or(warp::fs::dir("./html/leaderboard/")),
     ))
 }
 
 // * Dev server
+/// How long a new connection has to send its ID token
+const AUTH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 pub fn make_dev_server<'a, Socket, FutureSocket, Leaderboard>(
     name: &'a str,
     socket: Socket,
-    leaderboard: &'a Leaderboard,
+    leaderboard: Leaderboard,
 ) -> impl warp::Filter<Extract = impl warp::reply::Reply> + Clone + 'a
 where
-    Socket: Fn(String, warp::filters::ws::WebSocket) -> FutureSocket + Send + Sync + 'static,
+    Socket: Fn(String, Option<std::net::IpAddr>, warp::filters::ws::WebSocket) -> FutureSocket
+        + Send
+        + Sync
+        + 'static,
     FutureSocket: std::future::Future<Output = ()> + Send + 'static,
-    Leaderboard: Fn(Option<String>) -> warp::reply::Json + Sync,
+    Leaderboard: warp::Filter<Error = warp::reject::Rejection> + Clone + Send + Sync + 'static,
+    Leaderboard::Extract: warp::reply::Reply,
 {
     use std::fs::read_to_string;
     use warp::Filter;
@@ -191,15 +214,98 @@ where
             .or(warp::fs::dir(format!("./html/controller/{name}/"))),
     ));
 
+    // * `?lang=` wins over the browser's language
+    let language = warp::query::<std::collections::HashMap<String, String>>()
+        .and(warp::header::optional::<String>("accept-language"))
+        .map(
+            |query: std::collections::HashMap<String, String>, accept_language: Option<String>| {
+                query
+                    .get("lang")
+                    .or(accept_language.as_ref())
+                    .and_then(|language| i18n::negotiate(language))
+            },
+        );
+
+    // * The server runs behind a proxy, which passes the client's address on as the last hop.
+    // * Earlier hops are whatever the client sent, and only trusted proxies are believed at all
+    let address = warp::header::optional::<String>("x-forwarded-for")
+        .and(warp::addr::remote())
+        .map(
+            |forwarded: Option<String>, remote: Option<std::net::SocketAddr>| {
+                let remote = remote.map(|remote| remote.ip());
+                if !remote.is_some_and(|remote| config().queue.trusted_proxies.contains(&remote)) {
+                    return remote;
+                }
+                forwarded
+                    .and_then(|forwarded| forwarded.rsplit(',').next()?.trim().parse().ok())
+                    .or(remote)
+            },
+        );
+
     let socket = std::sync::Arc::new(socket);
     routes.or(warp::path("connect")
+        .and(warp::path(name))
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(language)
+        .and(address)
+        .map(
+            move |ws: warp::ws::Ws, language: Option<String>, address: Option<std::net::IpAddr>| {
+                let socket = socket.clone();
+                ws.on_upgrade(move |mut ws| async move {
+                    use futures_util::{SinkExt, StreamExt};
+
+                    // * The ID token is the first message, so it stays out of access logs
+                    let token = match tokio::time::timeout(AUTH_TIMEOUT, ws.next()).await {
+                        Ok(Some(Ok(message))) => message.to_str().ok().map(str::to_owned),
+                        _ => None,
+                    };
+                    let uid = match token {
+                        Some(token) => auth::verify_id_token(&token).await,
+                        None => None,
+                    };
+                    let Some(uid) = uid else {
+                        let language = language.unwrap_or_else(|| config().i18n.default.clone());
+                        let message = format!("!{}", tr(&language, "auth-invalid-token", &[]));
+                        log_error!("Send error: {}"; ws.send(warp::ws::Message::text(message)).await);
+                        return;
+                    };
+                    if let Some(language) = language {
+                        i18n::set_user_language(&uid, language);
+                    }
+                    socket(uid, address, ws).await
+                })
+            },
+        ))
+}
+
+// * Spectator server
+/// Read-only websockets at `/spectate/<name>/<target>`, they don't need an account
+/// and never enter the queue. What the decoded `target` means is up to the game
+pub fn make_spectator_server<'a, Spectator, FutureSpectator>(
+    name: &'a str,
+    spectator: Spectator,
+) -> impl warp::Filter<Extract = impl warp::reply::Reply> + Clone + 'a
+where
+    Spectator: Fn(String, warp::filters::ws::WebSocket) -> FutureSpectator + Send + Sync + 'static,
+    FutureSpectator: std::future::Future<Output = ()> + Send + 'static,
+{
+    use warp::Filter;
+
+    let spectator = std::sync::Arc::new(spectator);
+    warp::path("spectate")
         .and(warp::path(name))
         .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(warp::ws())
-        .map(move |uid, ws: warp::ws::Ws| {
-            let socket = socket.clone();
-            ws.on_upgrade(move |ws| socket(uid, ws))
-        }))
+        .map(move |target: String, ws: warp::ws::Ws| {
+            let spectator = spectator.clone();
+            // * Targets can be player names, which have spaces and such
+            let target = percent_encoding::percent_decode_str(&target)
+                .decode_utf8_lossy()
+                .into_owned();
+            ws.on_upgrade(move |ws| spectator(target, ws))
+        })
 }
 
 pub fn restart_async_server<F>(
@@ -253,15 +359,6 @@ pub fn color_from_u32(color: u32) -> Color {
     )
 }
 
-pub fn text_center_offset(context: &cairo::Context, text: &str) -> Option<vec2<f64>> {
-    context.text_extents(text).ok().map(|extents| {
-        vec2(
-            extents.width() / 2.0 + extents.x_bearing(),
-            extents.height() / 2.0 + extents.y_bearing(),
-        )
-    })
-}
-
 pub fn rounded_rectangle(
     context: &cairo::Context,
     x: f64,
@@ -290,49 +387,3 @@ pub fn load_wav(path: impl AsRef<std::path::Path>) -> soloud::audio::Wav {
     log_error!("Failed to load audio {}: {}", path.display(); wav.load(path));
     wav
 }
-
-// * ----------------------------------- Firebase ----------------------------------- * //
-pub async fn get_firebase_admin(
-) -> Option<rs_firebase_admin_sdk::App<rs_firebase_admin_sdk::GcpCredentials>> {
-    let firebase_credentials = try_log!(
-        "Failed to load firebase credentials: {}";
-        std::fs::read_to_string("firebase-private.json")
-        => None
-    );
-    let service_account = try_log!(
-        "Failed to load firebase auth data: {}";
-        rs_firebase_admin_sdk::CustomServiceAccount::from_json(&firebase_credentials)
-        => None
-    );
-    Some(try_log!(
-        "Failed to connect to firebase: {}";
-        rs_firebase_admin_sdk::App::live(service_account.into())
-        .await
-        => None
-    ))
-}
-
-pub async fn get_firebase_user(uid: String) -> Option<rs_firebase_admin_sdk::auth::User> {
-    if let Some(admin) = get_firebase_admin().await {
-        use rs_firebase_admin_sdk::auth::FirebaseAuthService;
-        log_error!(
-            "Error while fetching user: {}!";
-            admin
-                .auth()
-                .get_user(
-                    rs_firebase_admin_sdk::auth::UserIdentifiers::builder()
-                        .with_uid(uid)
-                        .build(),
-                )
-                .await
-        )
-        .and_then(|user| {
-            if user.is_none() {
-                log::error!("Error: User does not exist!");
-            }
-            user
-        })
-    } else {
-        None
-    }
-}
@@ -12,6 +12,8 @@ pub struct ScheduledPlugin {
     path: String,
     args: String,
     timestamp: DateTime<Local>,
+    /// Stream languages, alternated between if there are several
+    languages: Vec<String>,
 }
 
 impl ScheduledPlugin {
@@ -23,15 +25,30 @@ impl ScheduledPlugin {
         let (timestamp, command) = parts?;
         let (timestamp, command) = (timestamp.trim(), command.trim());
         let (path, args) = command.split_once(' ').unwrap_or((command, ""));
+
+        // * Date and time, optionally followed by comma separated languages
+        let mut fields = timestamp.split_whitespace();
+        let timestamp = format!(
+            "{} {}",
+            fields.next().unwrap_or_default(),
+            fields.next().unwrap_or_default()
+        );
+        let languages = fields
+            .flat_map(|languages| languages.split(','))
+            .filter(|language| !language.is_empty())
+            .map(str::to_owned)
+            .collect();
+
         let timestamp = try_log!(
             "Invalid schedule file, failed to parse timestamp: {}!";
-            chrono::NaiveDateTime::parse_from_str(timestamp.trim(), "%d.%m.%Y %H:%M:%S")
+            chrono::NaiveDateTime::parse_from_str(&timestamp, "%d.%m.%Y %H:%M:%S")
             => None
         );
         Some(Self {
             path: path.trim().to_owned(),
             args: args.trim().to_owned(),
             timestamp: Local.from_local_datetime(&timestamp).unwrap(),
+            languages,
         })
     }
 }
@@ -97,6 +114,8 @@ fn main() {
     struct LoadedPlugin<'a> {
         path: String,
         plugin: Plugin<'a>,
+        /// Last language passed to the plugin
+        language: Option<String>,
     }
 
     impl LoadedPlugin<'_> {
@@ -104,6 +123,7 @@ fn main() {
             Some(Self {
                 path: plugin.path.clone(),
                 plugin: Plugin::load(&plugin.path, &plugin.args)?,
+                language: None,
             })
         }
     }
@@ -164,6 +184,20 @@ fn main() {
             }
 
             if let Some(loaded_plugin) = &mut plugin {
+                let language = i18n::alternate(
+                    schedule
+                        .get(&loaded_plugin.path)
+                        .map_or(&[], |scheduled| scheduled.languages.as_slice()),
+                );
+                if loaded_plugin.language.as_ref() != Some(&language) {
+                    if let Some(set_language) = &loaded_plugin.plugin.language {
+                        unsafe {
+                            set_language(&language);
+                        }
+                    }
+                    loaded_plugin.language = Some(language);
+                }
+
                 let next = schedule.get_next(&loaded_plugin.path);
                 if !unsafe {
                     (loaded_plugin.plugin.frame)(
@@ -185,15 +219,16 @@ fn main() {
                     });
                 }
             } else {
-                context.set_source_rgb(1.0, 1.0, 1.0);
-                context.select_font_face(
-                    "Purisa",
-                    cairo::FontSlant::Normal,
-                    cairo::FontWeight::Normal,
-                );
+                theme().set_color(&context, "text");
+                theme().select_font(&context);
                 context.set_font_size(40.0);
                 context.move_to(20.0, 30.0);
-                log_error!("{}"; context.show_text("Nothing is scheduled!"));
+                let language = i18n::alternate(
+                    schedule
+                        .get_scheduled()
+                        .map_or(&[], |scheduled| scheduled.languages.as_slice()),
+                );
+                log_error!("{}"; context.show_text(&tr(&language, "nothing-scheduled", &[])));
             }
             if plugin.is_none() {
                 if let Some(scheduled) = schedule.get_scheduled() {
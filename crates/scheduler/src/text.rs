@@ -0,0 +1,214 @@
+use super::*;
+
+pub fn text_center_offset(context: &cairo::Context, text: &str) -> Option<vec2<f64>> {
+    context.text_extents(text).ok().map(|extents| {
+        vec2(
+            extents.width() / 2.0 + extents.x_bearing(),
+            extents.height() / 2.0 + extents.y_bearing(),
+        )
+    })
+}
+
+fn text_width(context: &cairo::Context, text: &str) -> f64 {
+    context
+        .text_extents(text)
+        .map_or(0.0, |extents| extents.x_advance())
+}
+
+/// Height of a line of text with the current font
+fn line_height(context: &cairo::Context) -> f64 {
+    context
+        .font_extents()
+        .map_or(0.0, |extents| extents.height())
+}
+
+/// Whether the text, wrapped with [`wrap_text`], fits into the box without ellipsizing a word
+fn fits_box(context: &cairo::Context, text: &str, max_width: f64, max_height: f64) -> bool {
+    text.split_whitespace()
+        .all(|word| text_width(context, word) <= max_width)
+        && wrap_text(context, text, max_width).len() as f64 * line_height(context) <= max_height
+}
+
+/// Set the largest font size up to `size` at which the text, wrapped with [`wrap_text`],
+/// fits into a `max_width` by `max_height` box, but not smaller than `min_size`.
+/// Returns false (with the font left at `min_size`) if the text doesn't fit even then
+pub fn fit_font_size(
+    context: &cairo::Context,
+    text: &str,
+    size: f64,
+    min_size: f64,
+    max_width: f64,
+    max_height: f64,
+) -> bool {
+    let fits = |size: f64| {
+        context.set_font_size(size);
+        fits_box(context, text, max_width, max_height)
+    };
+    if fits(size) {
+        return true;
+    }
+    if !fits(min_size) {
+        return false;
+    }
+
+    // * Binary search whole font sizes, a smaller font never needs more lines
+    let (mut fitting, mut too_big) = (min_size, size);
+    while too_big - fitting > 1.0 {
+        let middle = ((fitting + too_big) / 2.0).floor();
+        if fits(middle) {
+            fitting = middle;
+        } else {
+            too_big = middle;
+        }
+    }
+    context.set_font_size(fitting);
+    true
+}
+
+/// Cut the text short with "…" so it fits into `max_width` with the current font
+pub fn ellipsize(context: &cairo::Context, text: &str, max_width: f64) -> String {
+    if text_width(context, text) <= max_width {
+        return text.to_owned();
+    }
+    let shortened = |chars: usize| {
+        let end = text
+            .char_indices()
+            .nth(chars)
+            .map_or(text.len(), |(i, _)| i);
+        format!("{}…", text[..end].trim_end())
+    };
+
+    // * Binary search the longest prefix that fits, wider text never has a shorter prefix fit
+    let (mut fits, mut too_long) = (0, text.chars().count());
+    while too_long - fits > 1 {
+        let middle = (fits + too_long) / 2;
+        if text_width(context, &shortened(middle)) <= max_width {
+            fits = middle;
+        } else {
+            too_long = middle;
+        }
+    }
+    shortened(fits)
+}
+
+/// Break the text into lines that fit into `max_width` with the current font.
+/// Words that don't fit on a line by themselves are ellipsized
+pub fn wrap_text(context: &cairo::Context, text: &str, max_width: f64) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_owned()
+            } else {
+                format!("{line} {word}")
+            };
+            if text_width(context, &candidate) <= max_width {
+                line = candidate;
+            } else {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line = ellipsize(context, word, max_width);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Show lines stacked below each other with the current font, as a block vertically
+/// centered on `center_y`. Lines start at `x`, or are centered on it if `centered`
+pub fn show_lines(
+    context: &cairo::Context,
+    lines: &[String],
+    x: f64,
+    center_y: f64,
+    centered: bool,
+) {
+    let Some(extents) = log_error!("{}"; context.font_extents()) else {
+        return;
+    };
+    let mut y = center_y - lines.len() as f64 * extents.height() / 2.0 + extents.ascent();
+    for line in lines {
+        let x = if centered {
+            x - text_width(context, line) / 2.0
+        } else {
+            x
+        };
+        context.move_to(x, y.floor());
+        log_error!("{}"; context.show_text(line));
+        y += extents.height();
+    }
+}
+
+/// Text that scrolls sideways when it is too long for its box
+#[derive(Clone, Debug)]
+pub struct Marquee {
+    /// Pixels per second
+    pub speed: f64,
+    /// Space between the end of the text and its next repetition
+    pub gap: f64,
+    text: String,
+    offset: f64,
+}
+
+impl Default for Marquee {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+impl Marquee {
+    pub fn new(speed: f64) -> Self {
+        Self {
+            speed,
+            gap: 50.0,
+            text: String::new(),
+            offset: 0.0,
+        }
+    }
+
+    /// Draw the text with its baseline at `y`, clipped to `width`. The scroll restarts when the text changes
+    pub fn draw(
+        &mut self,
+        context: &cairo::Context,
+        text: &str,
+        x: f64,
+        y: f64,
+        width: f64,
+        frame_time: f64,
+    ) {
+        if self.text != text {
+            self.text = text.to_owned();
+            self.offset = 0.0;
+        }
+
+        let text_width = text_width(context, text);
+        if text_width <= width {
+            context.move_to(x, y);
+            log_error!("{}"; context.show_text(text));
+            return;
+        }
+
+        let period = text_width + self.gap;
+        self.offset = (self.offset + self.speed * frame_time) % period;
+
+        let Some(extents) = log_error!("{}"; context.font_extents()) else {
+            return;
+        };
+        log_error!("{}"; context.save());
+        context.rectangle(
+            x,
+            y - extents.ascent(),
+            width,
+            extents.ascent() + extents.descent(),
+        );
+        context.clip();
+        for start in [x - self.offset, x - self.offset + period] {
+            context.move_to(start, y);
+            log_error!("{}"; context.show_text(text));
+        }
+        log_error!("{}"; context.restore());
+    }
+}
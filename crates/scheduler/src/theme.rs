@@ -0,0 +1,101 @@
+use super::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Colors every theme has, missing ones are taken from here
+const DEFAULT_PALETTE: &[(&str, Color)] = &[
+    ("text", (1.0, 1.0, 1.0)),
+    ("lines", (1.0, 1.0, 1.0)),
+    ("board", (0.0, 0.2, 1.0)),
+    ("board_grid", (0.0, 0.1, 0.5)),
+    ("versus1", (0.96, 0.33, 0.33)),
+    ("versus2", (0.18, 0.38, 1.0)),
+    ("banner_background", (0.1, 0.1, 0.1)),
+    ("banner_border", (0.25, 0.6, 0.66)),
+    ("banner_text", (1.0, 1.0, 1.0)),
+    ("alert", (0.95, 0.3, 0.25)),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Font family of all on-stream text
+    pub font: String,
+    /// Named colors as `[r, g, b]` in 0..1
+    pub palette: HashMap<String, Color>,
+    /// Colors that tell players apart, reused from the start if there are more players
+    pub players: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            font: "Purisa".to_owned(),
+            palette: DEFAULT_PALETTE
+                .iter()
+                .map(|&(name, color)| (name.to_owned(), color))
+                .collect(),
+            players: vec![
+                (1.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+                (0.0, 0.0, 1.0),
+                (1.0, 1.0, 0.0),
+            ],
+        }
+    }
+}
+
+impl Theme {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Option<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return None;
+        }
+        let mut theme: Self = try_log!(
+            "Failed to parse theme {}: {}!", path.display();
+            toml::from_str(&try_log!(
+                "Failed to load theme {}: {}!", path.display();
+                std::fs::read_to_string(path)
+                => None
+            ))
+            => None
+        );
+        for &(name, color) in DEFAULT_PALETTE {
+            theme.palette.entry(name.to_owned()).or_insert(color);
+        }
+        if theme.players.is_empty() {
+            theme.players = Self::default().players;
+        }
+        Some(theme)
+    }
+
+    pub fn select_font(&self, context: &cairo::Context) {
+        context.select_font_face(
+            &self.font,
+            cairo::FontSlant::Normal,
+            cairo::FontWeight::Normal,
+        );
+    }
+
+    pub fn color(&self, name: &str) -> Color {
+        self.palette.get(name).copied().unwrap_or_else(|| {
+            log::warn!("Theme has no color {name:?}!");
+            (1.0, 0.0, 1.0)
+        })
+    }
+
+    pub fn set_color(&self, context: &cairo::Context, name: &str) {
+        let (r, g, b) = self.color(name);
+        context.set_source_rgb(r, g, b);
+    }
+
+    pub fn player(&self, index: usize) -> Color {
+        self.players[index % self.players.len()]
+    }
+}
+
+static THEME: std::sync::OnceLock<Theme> = std::sync::OnceLock::new();
+
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(|| Theme::load(&config().theme.path).unwrap_or_default())
+}
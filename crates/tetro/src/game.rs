@@ -101,7 +101,7 @@ impl Board {
         offset: vec2<f64>,
         frame_time: f64,
     ) {
-        context.set_source_rgb(0.0, 0.2, 1.0);
+        theme().set_color(context, "board");
         context.set_line_width(4.0);
         context.rectangle(
             offset.x,
@@ -111,7 +111,7 @@ impl Board {
         );
         log_error!("{}"; context.stroke());
 
-        context.set_source_rgb(0.0, 0.1, 0.5);
+        theme().set_color(context, "board_grid");
         context.set_line_width(1.0);
         for y in 0..self.size.y {
             for x in 0..self.size.x {
@@ -140,7 +140,7 @@ impl Board {
             }
         }
 
-        context.set_source_rgb(1.0, 1.0, 1.0);
+        theme().set_color(context, "lines");
         for y in &mut self.zone_lines {
             context.rectangle(
                 offset.x,
@@ -216,6 +216,7 @@ pub enum State {
 pub struct Game {
     pub uid: String,
     pub name: String,
+    pub match_id: String,
 
     pub board: Board,
     pub tetromino: Tetromino,
@@ -241,10 +242,11 @@ pub struct Game {
 }
 
 impl Game {
-    pub fn new(size: vec2<usize>, uid: String, name: String) -> Self {
+    pub fn new(size: vec2<usize>, uid: String, name: String, match_id: String) -> Self {
         Self {
             uid,
             name,
+            match_id,
 
             board: Board::new(size),
             tetromino: Tetromino::random(size.x / 2),
@@ -314,7 +316,7 @@ impl Game {
             .retain(|particle| particle.size.clone().move_by(0.0) > 0.0);
 
         let zone_pos = offset + (vec2(-2.1, 1.2) * tile).map(f64::floor);
-        context.set_source_rgb(0.0, 0.2, 1.0);
+        theme().set_color(context, "board");
         context.set_line_width(1.0);
         context.arc(
             zone_pos.x,
@@ -344,29 +346,33 @@ impl Game {
         );
         log_error!("{}"; context.stroke());
 
-        context.set_source_rgb(1.0, 1.0, 1.0);
+        theme().set_color(context, "text");
         context.set_font_size(tile);
 
-        if let Some(text_offset) = text_center_offset(context, &self.name) {
+        let name = ellipsize(context, &self.name, self.board.size.x as f64 * tile);
+        if let Some(text_offset) = text_center_offset(context, &name) {
             context.move_to(
                 offset.x + (self.board.size.x as f64 * tile) / 2.0 - text_offset.x,
                 offset.y - tile * 0.5,
             );
-            context.show_text(&self.name).ok();
+            context.show_text(&name).ok();
         }
 
         if let Some(text) = match self.state {
-            State::GameOver => Some("Lost"),
-            State::Won => Some("Won"),
+            State::GameOver => Some(tr(&i18n::stream_language(), "tetro-lost", &[])),
+            State::Won => Some(tr(&i18n::stream_language(), "tetro-won", &[])),
             _ => None,
         } {
-            if let Some(text_offset) = text_center_offset(context, text) {
-                context.move_to(
-                    offset.x + (self.board.size.x as f64 * tile) / 2.0 - text_offset.x,
-                    offset.y + tile * 2.5,
-                );
-                context.show_text(text).ok();
-            }
+            let board_width = self.board.size.x as f64 * tile;
+            fit_font_size(context, &text, tile, tile / 2.0, board_width, tile * 3.0);
+            show_lines(
+                context,
+                &wrap_text(context, &text, board_width),
+                offset.x + board_width / 2.0,
+                offset.y + tile * 2.0,
+                true,
+            );
+            context.set_font_size(tile);
         }
 
         let points = self.points.to_string();
@@ -460,6 +466,7 @@ impl Game {
                 soloud.play(&self.zone_finish);
                 self.state = State::Normal;
                 let zone_lines = self.board.zone_lines.len();
+                points::achievements::report(&self.uid, "tetro", "zone_lines", zone_lines as _);
                 if let Some(opponent) = opponent {
                     opponent
                         .board
@@ -551,15 +558,17 @@ impl Game {
     pub fn won(&mut self, tile: f64) {
         self.add_points(self.points);
         self.state = State::Won;
+        points::achievements::report(&self.uid, "tetro", "win", 1);
         self.endgame(tile);
     }
 
     pub fn endgame(&mut self, tile: f64) {
         if self.uid != "AI" {
             let uid = self.uid.clone();
+            let match_id = self.match_id.clone();
             let points = self.points;
             spawn_in_server_runtime(async move {
-                points::give(&uid, points).await;
+                points::give(&uid, points, "tetro", &match_id).await;
             });
         }
 
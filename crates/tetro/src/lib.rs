@@ -15,6 +15,7 @@ pub struct VSScreen {
 }
 
 pub struct State {
+    queue: queue::Queue,
     game: Option<[Game; 2]>,
     last_frame: std::time::Instant,
     vs_screen: Option<VSScreen>,
@@ -29,17 +30,34 @@ const GAME_SIZE: vec2<usize> = vec2(10, 20);
 #[allow(improper_ctypes_definitions)]
 pub extern "C" fn load(_: &str) {
     init_logger();
-    restart_async_server(async {
-        let routes = make_dev_server(
-            "tetro",
-            queue::make_queue(2, 50, Some(std::time::Duration::from_secs(30)), &socket),
-            points::make_leaderboard_server(),
-        );
-        routes
+    let queue = queue::Queue::new(
+        "tetro",
+        "tetro",
+        2,
+        50,
+        Some(std::time::Duration::from_secs(30)),
+    )
+    .with_ready_check(queue::ReadyCheck {
+        timeout: std::time::Duration::from_secs(15),
+        requeue: true,
+    });
+    restart_async_server({
+        let queue = queue.clone();
+        async move {
+            use warp::Filter;
+            let routes = make_dev_server(
+                queue.id(),
+                queue.handler(&socket),
+                queue.routes().or(points::make_leaderboard_server()),
+            )
+            .or(make_spectator_server(queue.id(), spectate));
+            routes
+        }
     });
 
     let mut state = STATE.lock().unwrap();
     *state = Some(State {
+        queue,
         game: None,
         last_frame: std::time::Instant::now(),
         vs_screen: None,
@@ -64,14 +82,18 @@ pub extern "C" fn frame(
     let frame_time = state.last_frame.elapsed().as_secs_f64();
     state.last_frame = std::time::Instant::now();
 
-    context.select_font_face(
-        "Purisa",
-        cairo::FontSlant::Normal,
-        cairo::FontWeight::Normal,
-    );
+    theme().select_font(&context);
 
-    let height =
-        height - points::make_bottom_banner(&context, width, height, time_left, last_event);
+    let height = height
+        - points::make_bottom_banner(
+            &context,
+            width,
+            height,
+            time_left,
+            last_event,
+            "tetro",
+            Some(state.queue.queue_length()),
+        );
 
     if let (Some(vs_screen), Some([game1, game2])) = (&mut state.vs_screen, &state.game) {
         let player1 = vec2(
@@ -85,19 +107,19 @@ pub extern "C" fn frame(
 
         context.set_font_size(height / 10.0);
         if let Some(offset) = text_center_offset(&context, "VS") {
-            context.set_source_rgb(1.0, 1.0, 1.0);
+            theme().set_color(&context, "text");
             context.move_to(width / 2.0 - offset.x, height / 2.0 - offset.y);
             log_error!("{}"; context.show_text("VS"));
         }
 
         context.set_font_size(height / 20.0);
-        context.set_source_rgb(0.96, 0.33, 0.33);
+        theme().set_color(&context, "versus1");
         context.move_to(player1.x, player1.y);
-        log_error!("{}"; context.show_text(&game1.name));
+        log_error!("{}"; context.show_text(&ellipsize(&context, &game1.name, width / 2.0)));
 
-        context.set_source_rgb(0.18, 0.38, 1.0);
+        theme().set_color(&context, "versus2");
         context.move_to(player2.x, player2.y);
-        log_error!("{}"; context.show_text(&game2.name));
+        log_error!("{}"; context.show_text(&ellipsize(&context, &game2.name, width / 2.0)));
 
         if vs_screen.player1.x.is_finished()
             && vs_screen.player1.y.is_finished()
@@ -109,26 +131,27 @@ pub extern "C" fn frame(
         return true;
     }
 
-    match queue::get_state() {
+    match state.queue.state() {
         queue::State::Playing
             if match &state.game {
                 Some([game1, game2]) => game1.uid == "AI" && game2.uid == "AI",
                 None => true,
             } =>
         {
-            let mut players = queue::get_players();
+            let mut players = state.queue.players();
             while players.len() < 2 {
                 players.push(("AI".to_owned(), "Builtin AI".to_owned()));
             }
+            let match_id = points::new_match_id();
             let games = players
                 .into_iter()
-                .map(|player| Game::new(GAME_SIZE, player.0, player.1))
+                .map(|player| Game::new(GAME_SIZE, player.0, player.1, match_id.clone()))
                 .collect::<Vec<_>>();
 
             context.set_font_size(height / 20.0);
             if let (Some(offset1), Some(offset2)) = (
-                text_center_offset(&context, &games[0].name),
-                text_center_offset(&context, &games[1].name),
+                text_center_offset(&context, &ellipsize(&context, &games[0].name, width / 2.0)),
+                text_center_offset(&context, &ellipsize(&context, &games[1].name, width / 2.0)),
             ) {
                 let vs_tween = |value_delta: f64, percent: f32| {
                     value_delta * ((percent * 2.0 - 1.0).powi(3) / 2.0 + 0.5) as f64
@@ -157,9 +180,20 @@ pub extern "C" fn frame(
         }
         queue::State::WaitingForPlayers(_) => {
             if state.game.is_none() {
+                let match_id = points::new_match_id();
                 state.game = Some([
-                    Game::new(GAME_SIZE, "AI".to_owned(), "Builtin AI".to_owned()),
-                    Game::new(GAME_SIZE, "AI".to_owned(), "Builtin AI".to_owned()),
+                    Game::new(
+                        GAME_SIZE,
+                        "AI".to_owned(),
+                        "Builtin AI".to_owned(),
+                        match_id.clone(),
+                    ),
+                    Game::new(
+                        GAME_SIZE,
+                        "AI".to_owned(),
+                        "Builtin AI".to_owned(),
+                        match_id,
+                    ),
                 ]);
             }
         }
@@ -181,14 +215,14 @@ pub extern "C" fn frame(
         game1.draw(&context, tile, offset1, frame_time);
         game2.draw(&context, tile, offset2, frame_time);
 
-        if let queue::State::Finished(time) = queue::get_state() {
+        if let queue::State::Finished(time) = state.queue.state() {
             if time.elapsed() > std::time::Duration::from_secs(5) {
                 if time_left < Duration::zero() {
                     kill_async_server();
                     return false;
                 }
                 state.game = None;
-                queue::restart();
+                state.queue.restart();
             }
         } else {
             // * Frames
@@ -210,17 +244,31 @@ pub extern "C" fn frame(
                     game2.won(tile);
                 }
 
-                queue::set_state(queue::State::Finished(std::time::Instant::now()));
+                queue::rating::report(
+                    "tetro",
+                    &[
+                        (game1.uid.clone(), if lost1 { 0.0 } else { 1.0 }),
+                        (game2.uid.clone(), if lost2 { 0.0 } else { 1.0 }),
+                    ],
+                );
+
+                state
+                    .queue
+                    .set_state(queue::State::Finished(std::time::Instant::now()));
             }
         }
     }
 
+    state.queue.draw_ready_check(&context, width, height);
+    state.queue.draw_up_next(&context, width, height);
+
     true
 }
 
 #[no_mangle]
 #[allow(improper_ctypes_definitions)]
 pub extern "C" fn command(command: &str) {
+    let queue = STATE.lock().unwrap().as_ref().unwrap().queue.clone();
     if command == "skip" {
         let mut state = STATE.lock().unwrap();
         let state = state.as_mut().unwrap();
@@ -229,16 +277,28 @@ pub extern "C" fn command(command: &str) {
             log::info!("Skipping game between {} and {}!", game1.name, game2.name);
             state.game = None;
         }
+    } else if !queue.command(command) && !points::command(command) {
+        log::error!("{command}: not a valid tetro command!");
     }
 }
 
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub extern "C" fn language(language: &str) {
+    i18n::set_stream_language(language);
+}
+
 fn socket(
     uid: String,
     name: String,
+    session: queue::Session,
+    connection: queue::moderation::Connection,
     mut tx: futures_util::stream::SplitSink<WebSocket, Message>,
     mut rx: futures_util::stream::SplitStream<WebSocket>,
 ) {
     tokio::spawn(async move {
+        // * Counts towards the connection limits until the session ends
+        let mut connection = connection;
         use futures_util::{SinkExt, StreamExt};
 
         macro_rules! try_send {
@@ -247,7 +307,27 @@ fn socket(
             };
         }
 
-        while let Some(Ok(message)) = rx.next().await {
+        // * Redraw the board right away instead of on the next ping
+        if session == queue::Session::Resumed {
+            let message = {
+                let state = STATE.lock().unwrap();
+                let state = state.as_ref().unwrap();
+                state
+                    .game
+                    .as_ref()
+                    .and_then(|games| games.iter().find(|game| game.uid == uid))
+                    .map(|game| Message::binary(game.build_message()))
+            };
+            if let Some(message) = message {
+                try_send!(message);
+            }
+        }
+
+        while let Some(Ok(message)) = tokio::select! {
+            message = rx.next() => message,
+            // * Banned players are disconnected right away
+            _ = connection.kicked() => None,
+        } {
             let pinged = if let Ok(command) = message.to_str() {
                 let mut state = STATE.lock().unwrap();
                 let state = state.as_mut().unwrap();
@@ -288,16 +368,31 @@ fn socket(
                         .as_ref()
                         .and_then(|games| games.iter().take(2).find(|game| game.uid == uid))
                     {
+                        // * The controller tells a win (`+`) from a loss (`-`) by the first character
                         if game.state == game::State::GameOver {
-                            (Message::text(format!("You lost :( But don't be disappointed! You've played well and got {} christmas decorations!", game.points)), true)
+                            (
+                                Message::text(format!(
+                                    "-{}",
+                                    tr(
+                                        &i18n::user_language(&uid),
+                                        "tetro-lost-message",
+                                        &[("points", &game.points)],
+                                    )
+                                )),
+                                true,
+                            )
                         } else if game.state == game::State::Won {
                             (
-                                    Message::text(format!(
-                                        "Celebrate, because you won! You've got {} christmas decorations!",
-                                        game.points
-                                    )),
-                                    true,
-                                )
+                                Message::text(format!(
+                                    "+{}",
+                                    tr(
+                                        &i18n::user_language(&uid),
+                                        "tetro-won-message",
+                                        &[("points", &game.points)],
+                                    )
+                                )),
+                                true,
+                            )
                         } else {
                             (Message::binary(game.build_message()), false)
                         }
@@ -317,3 +412,59 @@ fn socket(
         log::info!("{name} left.");
     });
 }
+
+/// Slot of the player with this uid or name in the games, or the slot index itself
+fn spectated_slot(games: Option<&[Game; 2]>, target: &str) -> Option<usize> {
+    let name = target.trim_start_matches('@');
+    games
+        .and_then(|games| {
+            games.iter().position(|game| {
+                game.uid != "AI"
+                    && (game.uid == target || game.name.trim_start_matches('@') == name)
+            })
+        })
+        .or_else(|| target.parse().ok().filter(|slot| *slot < 2))
+}
+
+/// Watch the board of a player by uid or name, following them through every match,
+/// or of a player slot, `0` or `1`. Like players, spectators get the board in reply to "Ping"
+async fn spectate(target: String, websocket: WebSocket) {
+    use futures_util::{SinkExt, StreamExt};
+    let (mut tx, mut rx) = websocket.split();
+
+    macro_rules! try_send {
+        ($message: expr) => {
+            try_log!("Send error: {}"; tx.send($message).await)
+        };
+    }
+
+    let found = STATE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|state| spectated_slot(state.game.as_ref(), &target).is_some());
+    if !found {
+        try_send!(Message::text(format!(
+            "!{}",
+            tr(&i18n::stream_language(), "spectate-no-player", &[])
+        )));
+        return;
+    }
+
+    while let Some(Ok(message)) = rx.next().await {
+        if message.to_str() != Ok("Ping") {
+            continue;
+        }
+        let message = {
+            let state = STATE.lock().unwrap();
+            let state = state.as_ref().unwrap();
+            state.game.as_ref().and_then(|games| {
+                let slot = spectated_slot(Some(games), &target)?;
+                Some(Message::binary(games[slot].build_message()))
+            })
+        };
+        if let Some(message) = message {
+            try_send!(message);
+        }
+    }
+}
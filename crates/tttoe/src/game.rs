@@ -25,12 +25,8 @@ impl Tag {
     }
 
     pub fn set_color(&self, context: &cairo::Context) {
-        match self {
-            Self::Cross => context.set_source_rgb(1.0, 0.0, 0.0),
-            Self::O => context.set_source_rgb(0.0, 1.0, 0.0),
-            Self::Triangle => context.set_source_rgb(0.0, 0.0, 1.0),
-            Self::Diamond => context.set_source_rgb(1.0, 1.0, 0.0),
-        }
+        let (r, g, b) = theme().player(*self as usize);
+        context.set_source_rgb(r, g, b);
     }
 
     pub fn draw(&self, context: &cairo::Context, offset: vec2<f64>, tile: f64, t: f64) {
@@ -129,6 +125,7 @@ impl Player {
 
 #[derive(Debug)]
 pub struct Game {
+    pub match_id: String,
     pub players: Vec<Player>,
     pub board: BidiVec<Option<(Tag, Instant)>>,
     pub turn: usize,
@@ -139,6 +136,7 @@ pub struct Game {
 impl Game {
     pub fn new(size: vec2<usize>, players: Vec<Player>) -> Self {
         Self {
+            match_id: points::new_match_id(),
             players,
             board: BidiVec::with_elem(None, size.x, size.y),
             turn: 0,
@@ -152,7 +150,7 @@ impl Game {
         let htile = (tile / 2.0).floor();
 
         context.set_line_cap(cairo::LineCap::Round);
-        context.set_source_rgb(1.0, 1.0, 1.0);
+        theme().set_color(context, "lines");
         context.set_line_width(3.0);
         context.rectangle(
             offset.x,
@@ -165,7 +163,7 @@ impl Game {
         for y in 0..self.board.height() {
             for x in 0..self.board.width() {
                 let offset = offset + vec2(x, y).map(|x| x as f64) * tile;
-                context.set_source_rgb(1.0, 1.0, 1.0);
+                theme().set_color(context, "lines");
                 context.rectangle(offset.x, offset.y, tile, tile);
                 log_error!("{}"; context.stroke());
 
@@ -184,7 +182,7 @@ impl Game {
             let p1 = offset + pos * tile + vec2::splat(htile) - dir * htile + dir * padding;
             let p2 = p1 + dir * STRIDE as f64 * tile - dir * padding * 2.0;
             let p2 = p1 + (p2 - p1) * time.elapsed().as_secs_f64().min(1.0);
-            context.set_source_rgb(1.0, 1.0, 1.0);
+            theme().set_color(context, "lines");
             context.move_to(p1.x, p1.y);
             context.line_to(p2.x, p2.y);
             log_error!("{}"; context.stroke());
@@ -198,7 +196,7 @@ impl Game {
             )
             .map(f64::floor);
 
-        context.set_font_size(htile - padding);
+        let text_width = self.board.width() as f64 * tile;
         for player in &self.players {
             let text = format!("{}: {}", player.name, player.score);
             player.tag.set_color(context);
@@ -208,15 +206,23 @@ impl Game {
                 htile - padding * 2.0,
                 1.0,
             );
-            if let Some(extents) = text_center_offset(context, &text) {
-                context.move_to(
-                    offset.x + htile + padding,
-                    offset.y + (htile / 2.0 - extents.y).floor(),
-                );
-                context.set_source_rgb(1.0, 1.0, 1.0);
-                log_error!("{}"; context.show_text(&text));
-                log_error!("{}"; context.stroke());
-            }
+            // * Long names wrap onto a second, smaller line
+            fit_font_size(
+                context,
+                &text,
+                htile - padding,
+                (htile - padding) / 2.0,
+                text_width,
+                htile,
+            );
+            theme().set_color(context, "text");
+            show_lines(
+                context,
+                &wrap_text(context, &text, text_width),
+                offset.x + htile + padding,
+                offset.y + htile / 2.0,
+                false,
+            );
             offset.y += htile;
         }
     }
@@ -278,12 +284,22 @@ impl Game {
             for player in &mut self.players {
                 if player.uid != "AI" {
                     let uid = player.uid.clone();
+                    let match_id = self.match_id.clone();
                     let score = player.score;
                     spawn_in_server_runtime(async move {
-                        points::give(&uid, score as _).await;
+                        points::give(&uid, score as _, "tttoe", &match_id).await;
                     });
+                    points::achievements::report(&player.uid, "tttoe", "lines", score as _);
                 }
             }
+            queue::rating::report(
+                "tttoe",
+                &self
+                    .players
+                    .iter()
+                    .map(|player| (player.uid.clone(), player.score as f64))
+                    .collect::<Vec<_>>(),
+            );
 
             false
         }else{true}
@@ -8,9 +8,16 @@ use warp::filters::ws::{Message, WebSocket};
 const PLAYERS: usize = 3;
 const GAME_SIZE: vec2<usize> = vec2(6, 6);
 const STRIDE: usize = 4;
+/// How long a player has for their turn
+const TURN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
 
 pub struct State {
+    queue: queue::Queue,
     game: Option<Game>,
+    /// Latest connection of every player, older ones stop once a player reconnects
+    sessions: std::collections::HashMap<String, u64>,
+    /// Notified whenever the game changes, wakes the player sockets
+    updates: tokio::sync::watch::Sender<()>,
 }
 
 static STATE: std::sync::Mutex<Option<State>> = std::sync::Mutex::new(None);
@@ -19,22 +26,38 @@ static STATE: std::sync::Mutex<Option<State>> = std::sync::Mutex::new(None);
 #[allow(improper_ctypes_definitions)]
 pub extern "C" fn load(_: &str) {
     init_logger();
-    restart_async_server(async {
-        let routes = make_dev_server(
-            "tttoe",
-            queue::make_queue(
-                PLAYERS,
-                50,
-                Some(std::time::Duration::from_secs(10)),
-                &socket,
-            ),
-            points::make_leaderboard_server(),
-        );
-        routes
+    let queue = queue::Queue::new(
+        "tttoe",
+        "tttoe",
+        PLAYERS,
+        50,
+        Some(std::time::Duration::from_secs(10)),
+    )
+    .with_ready_check(queue::ReadyCheck {
+        timeout: std::time::Duration::from_secs(15),
+        requeue: true,
+    });
+    restart_async_server({
+        let queue = queue.clone();
+        async move {
+            use warp::Filter;
+            let routes = make_dev_server(
+                queue.id(),
+                queue.handler(&socket),
+                queue.routes().or(points::make_leaderboard_server()),
+            )
+            .or(make_spectator_server(queue.id(), spectate));
+            routes
+        }
     });
 
     let mut state = STATE.lock().unwrap();
-    *state = Some(State { game: None });
+    *state = Some(State {
+        queue,
+        game: None,
+        sessions: std::collections::HashMap::new(),
+        updates: tokio::sync::watch::channel(()).0,
+    });
 }
 
 #[no_mangle]
@@ -51,23 +74,27 @@ pub extern "C" fn frame(
     let mut state = STATE.lock().unwrap();
     let state = state.as_mut().unwrap();
 
-    context.select_font_face(
-        "Purisa",
-        cairo::FontSlant::Normal,
-        cairo::FontWeight::Normal,
-    );
+    theme().select_font(&context);
 
-    let height =
-        height - points::make_bottom_banner(&context, width, height, time_left, last_event);
+    let height = height
+        - points::make_bottom_banner(
+            &context,
+            width,
+            height,
+            time_left,
+            last_event,
+            "tttoe",
+            Some(state.queue.queue_length()),
+        );
 
-    match queue::get_state() {
+    match state.queue.state() {
         queue::State::Playing
             if match &state.game {
                 Some(game) => game.players.iter().all(|player| player.uid == "AI"),
                 None => true,
             } =>
         {
-            let mut players = queue::get_players();
+            let mut players = state.queue.players();
             while players.len() < PLAYERS {
                 players.push(("AI".to_owned(), "Builtin AI".to_owned()));
             }
@@ -78,6 +105,7 @@ pub extern "C" fn frame(
                 .collect();
 
             state.game = Some(Game::new(GAME_SIZE, players));
+            state.updates.send_replace(());
         }
         queue::State::WaitingForPlayers(_) => {
             if state.game.is_none() {
@@ -107,29 +135,46 @@ pub extern "C" fn frame(
 
         game.draw(&context, tile, offset);
 
-        if let queue::State::Finished(time) = queue::get_state() {
+        if let queue::State::Finished(time) = state.queue.state() {
             if time.elapsed() > std::time::Duration::from_secs(5) {
                 if time_left < Duration::zero() {
                     kill_async_server();
                     return false;
                 }
                 state.game = None;
-                queue::restart();
+                state.queue.restart();
+                state.updates.send_replace(());
             }
         } else {
             // * Frames
+            let turn = game.turn;
+            // * Players without a connection don't hold up the game
+            if game.players[game.turn].uid != "AI"
+                && game.turn_timeout.elapsed() > TURN_TIMEOUT + std::time::Duration::from_secs(5)
+            {
+                game.skip_turn();
+            }
             if !game.update(soloud) {
-                queue::set_state(queue::State::Finished(std::time::Instant::now()));
+                state
+                    .queue
+                    .set_state(queue::State::Finished(std::time::Instant::now()));
+            }
+            if game.turn != turn {
+                state.updates.send_replace(());
             }
         }
     }
 
+    state.queue.draw_ready_check(&context, width, height);
+    state.queue.draw_up_next(&context, width, height);
+
     true
 }
 
 #[no_mangle]
 #[allow(improper_ctypes_definitions)]
 pub extern "C" fn command(command: &str) {
+    let queue = STATE.lock().unwrap().as_ref().unwrap().queue.clone();
     if command == "skip" {
         let mut state = STATE.lock().unwrap();
         let state = state.as_mut().unwrap();
@@ -144,23 +189,52 @@ pub extern "C" fn command(command: &str) {
             );
             state.game = None;
         }
+    } else if !queue.command(command) && !points::command(command) {
+        log::error!("{command}: not a valid tttoe command!");
     }
 }
 
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub extern "C" fn language(language: &str) {
+    i18n::set_stream_language(language);
+}
+
 fn socket(
     uid: String,
     name: String,
+    session: queue::Session,
+    connection: queue::moderation::Connection,
     mut tx: futures_util::stream::SplitSink<WebSocket, Message>,
     mut rx: futures_util::stream::SplitStream<WebSocket>,
 ) {
     use futures_util::{SinkExt, StreamExt};
+    static NEXT_SESSION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let id = NEXT_SESSION.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let Some(mut updates) = STATE.lock().unwrap().as_mut().map(|state| {
+        state.sessions.insert(uid.clone(), id);
+        // * Wakes an older session of the player, so it stops
+        state.updates.send_replace(());
+        state.updates.subscribe()
+    }) else {
+        return;
+    };
+    if session == queue::Session::Resumed {
+        log::info!("{name} is back.");
+    }
+
+    // * The full board is sent on every turn, so a resumed session needs nothing extra
     tokio::spawn(async move {
+        // * Counts towards the connection limits until the session ends
+        let mut connection = connection;
         while {
             let mut state = STATE.lock().unwrap();
             let state = state.as_mut().unwrap();
             state.game.is_none()
         } {
-            tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+            if updates.changed().await.is_err() {
+                return;
+            }
         }
 
         macro_rules! try_send {
@@ -170,15 +244,19 @@ fn socket(
         }
 
         loop {
-            let (message, my_turn) = {
+            let (message, my_turn, turn) = {
                 let mut state = STATE.lock().unwrap();
                 let state = state.as_mut().unwrap();
+                updates.borrow_and_update();
+                if state.sessions.get(&uid) != Some(&id) {
+                    break;
+                }
                 if let Some(game) = &state.game {
                     if let Some(my_turn) = game.players.iter().position(|player| player.uid == uid)
                     {
                         let mut message = game.build_message(&uid);
                         message.push((game.turn == my_turn) as u8);
-                        (Message::binary(message), game.turn == my_turn)
+                        (Message::binary(message), game.turn == my_turn, game.turn)
                     } else {
                         break;
                     }
@@ -190,36 +268,133 @@ fn socket(
             try_send!(message);
 
             if my_turn {
-                if let Ok(Some(Ok(message))) =
-                    tokio::time::timeout(tokio::time::Duration::from_secs(60), rx.next()).await
-                {
-                    if let Ok(command) = message.to_str() {
-                        if let Some((x, y)) = command.split_once(' ') {
-                            if let (Ok(x), Ok(y)) = (x.parse(), y.parse()) {
-                                let mut state = STATE.lock().unwrap();
-                                let state = state.as_mut().unwrap();
-                                if let Some(game) = &mut state.game {
-                                    game.try_turn(vec2(x, y));
-                                } else {
-                                    break;
+                let message = tokio::time::timeout(TURN_TIMEOUT, async {
+                    tokio::select! {
+                        message = rx.next() => message,
+                        // * Banned players are disconnected right away
+                        _ = connection.kicked() => None,
+                    }
+                });
+                match message.await {
+                    Ok(Some(Ok(message))) => {
+                        if let Ok(command) = message.to_str() {
+                            if let Some((x, y)) = command.split_once(' ') {
+                                if let (Ok(x), Ok(y)) = (x.parse(), y.parse()) {
+                                    let mut state = STATE.lock().unwrap();
+                                    let state = state.as_mut().unwrap();
+                                    if state.sessions.get(&uid) != Some(&id) {
+                                        break;
+                                    }
+                                    if let Some(game) = &mut state.game {
+                                        game.try_turn(vec2(x, y));
+                                        state.updates.send_replace(());
+                                    } else {
+                                        break;
+                                    }
                                 }
                             }
                         }
                     }
-                } else {
-                    let mut state = STATE.lock().unwrap();
-                    let state = state.as_mut().unwrap();
-                    if let Some(game) = &mut state.game {
-                        game.skip_turn();
-                    } else {
-                        break;
+                    // * Gone, the turn is skipped by the frame if they don't come back in time
+                    Ok(_) => break,
+                    Err(_) => {
+                        let mut state = STATE.lock().unwrap();
+                        let state = state.as_mut().unwrap();
+                        // * A newer session of the player has the turn now
+                        if state.sessions.get(&uid) != Some(&id) {
+                            break;
+                        }
+                        if let Some(game) = &mut state.game {
+                            if game.turn == turn {
+                                game.skip_turn();
+                                state.updates.send_replace(());
+                            }
+                        } else {
+                            break;
+                        }
                     }
                 }
             }
 
-            tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+            let changed = tokio::select! {
+                changed = updates.changed() => changed.is_ok(),
+                _ = connection.kicked() => false,
+            };
+            if !changed {
+                break;
+            }
         }
 
         log::info!("{name} left.");
     });
 }
+
+/// Slot of the player with this uid or name in the game, or the slot index itself
+fn spectated_slot(game: Option<&Game>, target: &str) -> Option<usize> {
+    let name = target.trim_start_matches('@');
+    game.and_then(|game| {
+        game.players.iter().position(|player| {
+            player.uid != "AI"
+                && (player.uid == target || player.name.trim_start_matches('@') == name)
+        })
+    })
+    .or_else(|| target.parse().ok().filter(|slot| *slot < PLAYERS))
+}
+
+/// Watch the board of a player by uid or name, following them through every match,
+/// or of a player slot, `0` to `PLAYERS - 1`
+async fn spectate(target: String, websocket: WebSocket) {
+    use futures_util::{SinkExt, StreamExt};
+    let (mut tx, mut rx) = websocket.split();
+
+    macro_rules! try_send {
+        ($message: expr) => {
+            try_log!("Send error: {}"; tx.send($message).await)
+        };
+    }
+
+    let found = STATE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|state| spectated_slot(state.game.as_ref(), &target).is_some());
+    if !found {
+        try_send!(Message::text(format!(
+            "!{}",
+            tr(&i18n::stream_language(), "spectate-no-player", &[])
+        )));
+        return;
+    }
+    let Some(mut updates) = STATE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|state| state.updates.subscribe())
+    else {
+        return;
+    };
+
+    // * Spectators only listen, their messages are dropped until they leave
+    tokio::spawn(async move { while let Some(Ok(_)) = rx.next().await {} });
+
+    loop {
+        let message = {
+            let state = STATE.lock().unwrap();
+            let state = state.as_ref().unwrap();
+            updates.borrow_and_update();
+            state.game.as_ref().and_then(|game| {
+                let slot = spectated_slot(Some(game), &target)?;
+                let player = game.players.get(slot)?;
+                let mut message = game.build_message(&player.uid);
+                message.push((game.turn == slot) as u8);
+                Some(Message::binary(message))
+            })
+        };
+        if let Some(message) = message {
+            try_send!(message);
+        }
+        if updates.changed().await.is_err() {
+            break;
+        }
+    }
+}
@@ -18,4 +18,7 @@ const requireAuth = (callback) => {
             document.location.href = `${document.location.origin}/account/sign-in.html?destination=${document.location.href}`;
         }
     });
-};
\ No newline at end of file
+};
+
+// * ID tokens expire after an hour, so get one right before connecting. Firebase refreshes it when needed
+const getIdToken = () => firebase.auth().currentUser.getIdToken();
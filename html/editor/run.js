@@ -47,7 +47,7 @@ function error(text) {
 setInterval(() => $('#console').scrollTop($('#console')[0].scrollHeight), 25);
 
 let worker;
-const run = (code, language) => {
+const run = async (code, language) => {
     // for (let task of backgroundTasks) {
     //     clearInterval(task);
     // }
@@ -70,7 +70,8 @@ const run = (code, language) => {
     worker.postMessage({
         code,
         language,
-        connectionURL: `${window.location.protocol === 'https:' ? 'wss' : 'ws'}://${document.location.host}/connect/$NAME/${account.uid}`
+        connectionURL: `${window.location.protocol === 'https:' ? 'wss' : 'ws'}://${document.location.host}/connect/$NAME`,
+        idToken: await getIdToken()
     });
 };
 
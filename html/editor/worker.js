@@ -8,7 +8,7 @@ function error(message) {
     self.postMessage({ error: message });
 }
 
-let socket, websocketURL;
+let socket, websocketURL, token;
 
 function connect(onMessage) {
     socket = new WebSocket(websocketURL.replace("$NAME", apiName));
@@ -23,12 +23,16 @@ function connect(onMessage) {
         }
         processMessage(msg, onMessage);
     };
-    socket.onopen = () => socket.send("Ping");
+    socket.onopen = () => {
+        socket.send(token);
+        socket.send("Ping");
+    };
 }
 
 self.onmessage = function (event) {
-    const { code, language, connectionURL } = event.data;
+    const { code, language, connectionURL, idToken } = event.data;
     websocketURL = connectionURL;
+    token = idToken;
 
     if (language == "ace/mode/javascript") {
         new Function(code)();